@@ -1,6 +1,6 @@
 use std::{cell::RefCell, ops::{Range, RangeFrom, RangeTo}};
 
-use nom::{branch::alt, bytes::complete::tag, character::{complete::{alpha1, alphanumeric1, multispace1, not_line_ending}, streaming::one_of}, combinator::{eof, map, recognize}, error::{ErrorKind, ParseError}, multi::{many0, many1, many_till}, sequence::{delimited, pair, preceded, tuple}, AsChar, Compare, CompareResult, IResult, InputIter, InputLength, Slice};
+use nom::{branch::alt, bytes::complete::{tag, take_till}, character::{complete::{alpha1, alphanumeric1, hex_digit1, multispace1, not_line_ending}, streaming::one_of}, combinator::{eof, map, map_opt, recognize}, error::{ErrorKind, ParseError}, multi::{many0, many1, many_till}, sequence::{delimited, pair, preceded, tuple}, AsChar, Compare, CompareResult, IResult, InputIter, InputLength, Slice};
 
 use crate::{combinators::map_with_span, token::{FromStrSpan, Keyword, ParseState, RecoveredError, StrResult, StrSpan, ToRange, TokSpan, Token}};
 
@@ -65,6 +65,33 @@ fn lexer<'a, 'b>(input: StrSpan<'a, 'b>) -> StrResult<StrSpan<'a, 'b>, Vec<TokSp
         }
     );
 
+    let string = map(recognize(tuple((
+            tag("\""),
+            take_till(|c| c == '"'),
+            tag("\"")
+        ))),
+        |s: StrSpan| {
+            let content = &s.fragment()[1..s.fragment().len()-1];
+            TokSpan::from_strspan(Token::Str{content}, s.extra, s.span())
+        }
+    );
+
+    let hex_color = map_opt(recognize(pair(
+            tag("#"),
+            hex_digit1
+        )),
+        |s: StrSpan| {
+            let digits = &s.fragment()[1..];
+            let channel = |index: usize| u8::from_str_radix(&digits[index*2..index*2+2], 16).unwrap();
+            let token = match digits.len() {
+                6 => Token::HexColor(channel(0), channel(1), channel(2), 255),
+                8 => Token::HexColor(channel(0), channel(1), channel(2), channel(3)),
+                _ => return None
+            };
+            Some(TokSpan::from_strspan(token, s.extra, s.span()))
+        }
+    );
+
     let ctrl = map(
         alt((
             tag("("),
@@ -104,6 +131,8 @@ fn lexer<'a, 'b>(input: StrSpan<'a, 'b>) -> StrResult<StrSpan<'a, 'b>, Vec<TokSp
                 "profile" => Token::K(Keyword::Profile),
                 "macro" => Token::K(Keyword::Macro),
                 "loop" => Token::K(Keyword::Loop),
+                "device" => Token::K(Keyword::Device),
+                "desk" => Token::K(Keyword::Desk),
                 "true" => Token::Bool(true),
                 "false" => Token::Bool(false),
                 s => Token::Ident{ name: s },
@@ -116,6 +145,8 @@ fn lexer<'a, 'b>(input: StrSpan<'a, 'b>) -> StrResult<StrSpan<'a, 'b>, Vec<TokSp
     let token = alt((
         float,
         integer,
+        string,
+        hex_color,
         ctrl,
         ident,
     ));
@@ -2,7 +2,7 @@ use std::{cell::RefCell, collections::HashMap, time::Duration};
 
 use cgmath::Deg;
 use parser::token_parse;
-use sis_core::{ColorAnimation, RippleAnimation, VirtualKey, WaveAnimation};
+use sis_core::{AudioAnimation, AudioMode, BlendMode, Calibration, ColorAnimation, ColorChangeAnimation, ColorChangeMode, DevicePoint, DeviceTarget, DeviceType, FireAnimation, FlashAnimation, GaugeAnimation, GaugeMode, HeatMapAnimation, ImageAnimation, ImageFit, KeyRippleAnimation, LinearGradient, Metric, Placement, PlasmaAnimation, RadialGradient, RainbowSpiralAnimation, Repeat, RippleAnimation, StarfieldAnimation, Timing, VirtualKey, WaveAnimation};
 use statement::{Color, FuncName, Statement, Value};
 use token::{Spanned, Token};
 
@@ -11,11 +11,51 @@ mod parser;
 mod token;
 mod combinators;
 mod statement;
+mod zones;
 
 pub struct Config {
     pub profiles: Vec<Profile>,
     pub macros: Vec<Macro>,
-    pub color_animations: Vec<ColorAnimation>
+    pub color_animations: Vec<ColorAnimation>,
+    /// Where each device is. Devices without a placement stay where they are
+    pub desk: Vec<Placement>,
+    pub settings: Settings,
+}
+
+/// Devices can't be updated much faster
+const MAX_FPS: i32 = 120;
+/// How much `brightness_up()` and `brightness_down()` change the brightness
+const BRIGHTNESS_STEP: f64 = 0.1;
+
+/// What the `settings` block sets, for every profile
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// How long switching profiles fades from the lights of one to the other, unless `switch_profile` sets it
+    pub profile_fade: Duration,
+    /// Frames rendered per second, while there are animated effects
+    pub fps: u32,
+    /// Between 0.0 and 1.0, every color is multiplied by it. Macros can change it
+    pub brightness: f64,
+    /// Colors are raised to this power before being sent to the devices, 1.0 leaves them as they are
+    pub gamma: f64,
+    /// The first calibration that targets a device is used
+    pub calibrations: Vec<Calibration>,
+}
+
+impl Settings {
+    pub const DEFAULT: Settings = Settings {
+        profile_fade: Duration::ZERO,
+        fps: 30,
+        brightness: 1.0,
+        gamma: 1.0,
+        calibrations: Vec::new(),
+    };
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings::DEFAULT
+    }
 }
 
 pub fn get_config() -> Config {
@@ -34,6 +74,9 @@ pub fn get_config() -> Config {
     let mut macros = HashMap::new();
     let mut profiles = HashMap::new();
     let mut color_animations = HashMap::new();
+    let mut desk = Vec::new();
+    let mut settings = Settings::default();
+    let mut zones = zones::builtin_zones();
 
     for (statement, _) in statements {
         match statement {
@@ -78,7 +121,52 @@ pub fn get_config() -> Config {
                     break;
                 }
             },
-            Statement::Loop { .. } => {
+            Statement::Desk { body } => {
+                for statement in body {
+                    match statement {
+                        Statement::Call { name: (FuncName::Place, _), args } => desk.push(get_placement(&args).unwrap()),
+                        _ => {
+                            eprintln!("Only place(...) can be used inside desk");
+                            break;
+                        },
+                    }
+                }
+            },
+            Statement::Settings { body } => {
+                for statement in body {
+                    match statement {
+                        Statement::Call { name: (FuncName::ProfileFade, _), args } => settings.profile_fade = match args.as_slice() {
+                            [(millis, _)] => get_millis(millis).unwrap(),
+                            _ => panic!("profile_fade takes the fade in milliseconds"),
+                        },
+                        Statement::Call { name: (FuncName::Fps, _), args } => settings.fps = match args.as_slice() {
+                            [(Value::Integer(fps), _)] if (1..=MAX_FPS).contains(fps) => *fps as u32,
+                            _ => panic!("fps takes the frames per second, between 1 and {MAX_FPS}"),
+                        },
+                        Statement::Call { name: (FuncName::Brightness, _), args } => settings.brightness = match args.as_slice() {
+                            [(brightness, _)] => get_brightness(brightness).unwrap(),
+                            _ => panic!("brightness takes a brightness between 0.0 and 1.0"),
+                        },
+                        Statement::Call { name: (FuncName::Gamma, _), args } => settings.gamma = match args.as_slice() {
+                            [(Value::Float(gamma), _)] if *gamma > 0.0 => *gamma as f64,
+                            _ => panic!("gamma takes a number bigger than 0.0, like 2.2"),
+                        },
+                        Statement::Call { name: (FuncName::Calibrate, _), args } => settings.calibrations.push(get_calibration(&args).unwrap()),
+                        _ => {
+                            eprintln!("Only settings (like profile_fade(...)) can be used inside settings");
+                            break;
+                        },
+                    }
+                }
+            },
+            Statement::Zone { name, keys } => {
+                let keys = get_zone(&keys.0, &zones).unwrap();
+                if let Some(_) = zones.insert(name.clone(), keys) {
+                    eprintln!("Redefinition of zone {name}");
+                    break;
+                }
+            },
+            Statement::Loop { .. } | Statement::Device { .. } => {
                 eprintln!("Invalid AST");
                 break;
             },
@@ -86,7 +174,7 @@ pub fn get_config() -> Config {
     }
 
     let profile_names = profiles.iter().map(|(name, _)| name.as_str()).collect();
-    expand_functions(&mut functions, &mut Vec::new(), &color_animations, &profile_names).unwrap();
+    expand_functions(&mut functions, &mut Vec::new(), &color_animations, &zones, &profile_names).unwrap();
     let functions = functions.into_iter()
         .map(|(name, actions)| {
             let actions = match actions {
@@ -112,14 +200,16 @@ pub fn get_config() -> Config {
 
     let mut expanded_profiles = Vec::new();
     for (profile_name, profile_actions) in profiles {
-        let profile = create_profile(profile_name, profile_actions, &functions, &color_animations).unwrap();
+        let profile = create_profile(profile_name, profile_actions, &functions, &color_animations, &zones).unwrap();
         expanded_profiles.push(profile)
     }
 
     Config {
         profiles: expanded_profiles,
         macros: expanded_macros,
-        color_animations: color_animations.into_iter().map(|(_name, animation)| animation).collect()
+        color_animations: color_animations.into_iter().map(|(_name, animation)| animation).collect(),
+        desk,
+        settings,
     }
 }
 
@@ -129,12 +219,13 @@ pub struct Profile {
     pub loop_actions: Vec<Action>
 }
 
-fn create_profile(profile_name: String, profile_actions: Calls, functions: &HashMap<FuncName, Actions>, animations: &HashMap<String, ColorAnimation>) -> Result<Profile, ()> {
+fn create_profile(profile_name: String, profile_actions: Calls, functions: &HashMap<FuncName, Actions>, animations: &HashMap<String, ColorAnimation>, zones: &HashMap<String, Vec<VirtualKey>>) -> Result<Profile, ()> {
     let mut actions = Vec::new();
     let mut loop_ = Vec::new();
-    for (in_loop, ((action_name, _), args)) in profile_actions.loop_.into_iter().map(|a| (true, a)).chain(profile_actions.one_time.into_iter().map(|a| (false, a))) {
-        match get_action(&action_name, &args, animations)? {
+    for (in_loop, ((action_name, _), args, target)) in profile_actions.loop_.into_iter().map(|a| (true, a)).chain(profile_actions.one_time.into_iter().map(|a| (false, a))) {
+        match get_action(&action_name, &args, animations, zones)? {
             Some(action) => {
+                let action = on_device(action, &target);
                 if in_loop {
                     loop_.push(action)
                 } else {
@@ -151,8 +242,8 @@ fn create_profile(profile_name: String, profile_actions: Calls, functions: &Hash
                         loop_: l,
                         one_time: ot
                     } = function;
-                    actions.extend(ot.iter().map(|a| a.clone()));
-                    loop_.extend(l.iter().map(|a| a.clone()));
+                    actions.extend(ot.iter().map(|a| on_device(a.clone(), &target)));
+                    loop_.extend(l.iter().map(|a| on_device(a.clone(), &target)));
                 } else {
                     return Err(());
                 }
@@ -168,9 +259,10 @@ pub struct Macro {
     pub actions: Vec<Action>
 }
 
-fn create_macro(macro_name: String, profile_actions: Vec<(Spanned<FuncName>, Vec<Spanned<Value>>)>, profile_names: &[&str]) -> Result<Macro, ()> {
+fn create_macro(macro_name: String, profile_actions: Vec<Call>, profile_names: &[&str]) -> Result<Macro, ()> {
     let mut actions = Vec::new();
-    for ((action_name, _), args) in profile_actions {
+    // Device blocks only make sense for light effects, which macros can't have
+    for ((action_name, _), args, _target) in profile_actions {
         match action_name {
             FuncName::SetHotkey => todo!(),
             FuncName::PressKey => {
@@ -185,6 +277,12 @@ fn create_macro(macro_name: String, profile_actions: Vec<(Spanned<FuncName>, Vec
                 let switch_profile_action = get_switch_profile_action(&args, profile_names)?;
                 actions.push(switch_profile_action);
             },
+            FuncName::SetBrightness => match args.as_slice() {
+                [(brightness, _)] => actions.push(Action::SetBrightness(get_brightness(brightness)?)),
+                _ => return Err(())
+            },
+            FuncName::BrightnessUp if args.is_empty() => actions.push(Action::ChangeBrightness(BRIGHTNESS_STEP)),
+            FuncName::BrightnessDown if args.is_empty() => actions.push(Action::ChangeBrightness(-BRIGHTNESS_STEP)),
             _ => return Err(())
         }
     }
@@ -192,9 +290,12 @@ fn create_macro(macro_name: String, profile_actions: Vec<(Spanned<FuncName>, Vec
     Ok(Macro{ name: macro_name, actions })
 }
 
+/// A call, and the devices its light effects apply to
+type Call = (Spanned<FuncName>, Vec<Spanned<Value>>, DeviceTarget);
+
 struct Calls {
-    loop_: Vec<(Spanned<FuncName>, Vec<Spanned<Value>>)>,
-    one_time: Vec<(Spanned<FuncName>, Vec<Spanned<Value>>)>
+    loop_: Vec<Call>,
+    one_time: Vec<Call>
 }
 
 fn get_calls(body: Vec<Statement>) -> Calls {
@@ -202,7 +303,20 @@ fn get_calls(body: Vec<Statement>) -> Calls {
     let mut loop_ = Vec::new();
     for statement in body {
         match statement {
-            Statement::Call { name, args } => func_calls.push((name, args)),
+            Statement::Call { name, args } => func_calls.push((name, args, DeviceTarget::All)),
+            Statement::Device { target: (target, _), body } => {
+                let Calls {
+                    loop_: l,
+                    one_time: funcs,
+                } = get_calls(body);
+                // The innermost device block wins
+                let set_target = |(name, args, inner_target): Call| match inner_target {
+                    DeviceTarget::All => (name, args, target.clone()),
+                    _ => (name, args, inner_target),
+                };
+                loop_.extend(l.into_iter().map(set_target));
+                func_calls.extend(funcs.into_iter().map(set_target));
+            },
             Statement::Loop { body } => {
                 let Calls {
                     loop_: l,
@@ -217,6 +331,9 @@ fn get_calls(body: Vec<Statement>) -> Calls {
             Statement::Func { .. } => unreachable!(),
             Statement::Macro { .. } => unreachable!(),
             Statement::ColorAnimation { .. } => unreachable!(),
+            Statement::Desk { .. } => unreachable!(),
+            Statement::Zone { .. } => unreachable!(),
+            Statement::Settings { .. } => unreachable!(),
         }
     }
 
@@ -226,14 +343,14 @@ fn get_calls(body: Vec<Statement>) -> Calls {
     }
 }
 
-fn expand_functions(functions_ptr: *mut HashMap<String, MaybeExpanded>, call_stack: &mut Vec<String>, animations: &HashMap<String, ColorAnimation>, profile_names: &Vec<&str>) -> Result<(), ()> {
+fn expand_functions(functions_ptr: *mut HashMap<String, MaybeExpanded>, call_stack: &mut Vec<String>, animations: &HashMap<String, ColorAnimation>, zones: &HashMap<String, Vec<VirtualKey>>, profile_names: &Vec<&str>) -> Result<(), ()> {
     let functions = unsafe{
         functions_ptr.as_mut().unwrap()
     };
 
     for (function_name, function_actions) in functions.iter_mut() {
         if let MaybeExpanded::NotExpanded(not_expanded_function_actions) = function_actions {
-            *function_actions = MaybeExpanded::Expanded(expand_function(functions_ptr, call_stack, function_name.clone(), &not_expanded_function_actions, animations, profile_names).unwrap());
+            *function_actions = MaybeExpanded::Expanded(expand_function(functions_ptr, call_stack, function_name.clone(), &not_expanded_function_actions, animations, zones, profile_names).unwrap());
         } else if let MaybeExpanded::Expanded(_) = function_actions {
             // Do nothing, it is already expanded
         }
@@ -254,6 +371,7 @@ fn expand_function(
     function_name: String,
     function_actions: &Calls,
     animations: &HashMap<String, ColorAnimation>,
+    zones: &HashMap<String, Vec<VirtualKey>>,
     profile_names: &[&str]
 ) -> Result<Actions, ()> {
     let functions = unsafe{
@@ -272,9 +390,10 @@ fn expand_function(
     let mut actions = Vec::new();
     let mut loop_ = Vec::new();
 
-    for (in_loop, ((action_name, _), args)) in l.iter().map(|a| (true, a)).chain(ot.iter().map(|a| (false, a))) {
-        match get_action(action_name, &args, animations) {
+    for (in_loop, ((action_name, _), args, target)) in l.iter().map(|a| (true, a)).chain(ot.iter().map(|a| (false, a))) {
+        match get_action(action_name, &args, animations, zones) {
             Ok(Some(action)) => {
+                let action = on_device(action, target);
                 if in_loop {
                     loop_.push(action)
                 } else {
@@ -289,14 +408,14 @@ fn expand_function(
                 let function = functions.get_mut(action_name);
                 if let Some(function) = function {
                     if let MaybeExpanded::NotExpanded(not_expanded_function) = function {
-                        if let Ok(acts) = expand_function(functions_ptr, call_stack, action_name.clone(), not_expanded_function, animations, profile_names) {
+                        if let Ok(acts) = expand_function(functions_ptr, call_stack, action_name.clone(), not_expanded_function, animations, zones, profile_names) {
                             *function = MaybeExpanded::Expanded(acts.clone());
                             let Actions {
                                 loop_: l,
                                 one_time: ot
                             } = acts;
-                            actions.extend(ot.into_iter());
-                            loop_.extend(l.into_iter());
+                            actions.extend(ot.into_iter().map(|a| on_device(a, target)));
+                            loop_.extend(l.into_iter().map(|a| on_device(a, target)));
                         } else {
                             call_stack.pop();
                             return Err(())
@@ -306,8 +425,8 @@ fn expand_function(
                             loop_: l,
                             one_time: ot,
                         } = function;
-                        actions.extend(ot.iter().map(|a| a.to_owned()));
-                        loop_.extend(l.iter().map(|a| a.to_owned()));
+                        actions.extend(ot.iter().map(|a| on_device(a.to_owned(), target)));
+                        loop_.extend(l.iter().map(|a| on_device(a.to_owned(), target)));
                     }
                 } else {
                     call_stack.pop();
@@ -328,8 +447,23 @@ fn expand_function(
     })
 }
 
-fn get_action(action_name: &FuncName, args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Option<Action>, ()> {
-    Ok(match action_name {
+/// Restricts a light effect to `target`. Other actions (like hotkeys) aren't tied to a device, and effects
+/// that already have a target keep it, since it comes from a more specific device block.
+fn on_device(action: Action, target: &DeviceTarget) -> Action {
+    match (target, &action) {
+        (DeviceTarget::All, _) => action,
+        (_, Action::KeyEffect(..) | Action::Zone(..)) => Action::OnDevice(target.clone(), Box::new(action)),
+        (_, action) if action.is_light_effect() => Action::OnDevice(target.clone(), Box::new(action.clone())),
+        _ => action,
+    }
+}
+
+fn get_action(action_name: &FuncName, args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>, zones: &HashMap<String, Vec<VirtualKey>>) -> Result<Option<Action>, ()> {
+    let (args, zone) = take_zone(args, zones)?;
+    let (args, blend) = take_blend(&args)?;
+    let (args, timing) = take_timing(&args)?;
+    let args = args.as_slice();
+    let action = match action_name {
         FuncName::SetHotkey => {
             Some(get_hotkey_action(args)?)
         },
@@ -339,10 +473,217 @@ fn get_action(action_name: &FuncName, args: &[Spanned<Value>], animations: &Hash
         FuncName::WaveEffect => Some(get_wave_effect_action(args, animations)?),
         FuncName::RippleEffect => Some(get_ripple_effect_action(args, animations)?),
         FuncName::StaticColor => Some(get_static_color_action(args)?),
+        FuncName::KeyColor => Some(get_key_color_action(args)?),
+        FuncName::KeyEffect => Some(get_key_effect_action(args, animations, zones)?),
+        FuncName::FlashEffect => Some(get_flash_effect_action(args, animations)?),
+        FuncName::KeyRippleEffect => Some(get_key_ripple_effect_action(args, animations)?),
+        FuncName::HeatMapEffect => Some(get_heat_map_effect_action(args, animations)?),
+        FuncName::ColorChangeEffect => Some(get_color_change_effect_action(args, animations)?),
+        FuncName::RainbowSpiralEffect => Some(get_rainbow_spiral_effect_action(args)?),
+        FuncName::StarfieldEffect => Some(get_starfield_effect_action(args)?),
+        FuncName::FireEffect => Some(get_fire_effect_action(args, animations)?),
+        FuncName::PlasmaEffect => Some(get_plasma_effect_action(args, animations)?),
+        FuncName::LinearGradient => Some(get_linear_gradient_action(args, animations)?),
+        FuncName::RadialGradient => Some(get_radial_gradient_action(args, animations)?),
+        FuncName::ImageEffect => Some(get_image_effect_action(args)?),
+        FuncName::AudioEffect => Some(get_audio_effect_action(args, animations)?),
+        FuncName::GaugeEffect => Some(get_gauge_effect_action(args, animations)?),
+        FuncName::Place | FuncName::ProfileFade | FuncName::Fps | FuncName::Brightness | FuncName::Gamma | FuncName::Calibrate => return Err(()),
+        FuncName::SetBrightness | FuncName::BrightnessUp | FuncName::BrightnessDown => return Err(()),
         FuncName::Other(_) => None,
+    };
+
+    let action = match (action, timing) {
+        (Some(action), Some(timing)) if action.is_light_effect() => Some(Action::Timed(timing, Box::new(action))),
+        (_, Some(_)) => return Err(()), // Only light effects have a timing
+        (action, None) => action,
+    };
+    let action = match (action, blend) {
+        (Some(action), Some(blend)) if action.is_light_effect() => Some(Action::Blend(blend, Box::new(action))),
+        (_, Some(_)) => return Err(()), // Only light effects can be blended
+        (action, None) => action,
+    };
+    match (action, zone) {
+        (Some(action), Some(zone)) if action.is_light_effect() => Ok(Some(Action::Zone(zone, Box::new(action)))),
+        (_, Some(_)) => Err(()), // Only light effects can be masked to a zone
+        (action, None) => Ok(action),
+    }
+}
+
+/// Removes the `zone = ...` argument, that every light effect can have
+fn take_zone(args: &[Spanned<Value>], zones: &HashMap<String, Vec<VirtualKey>>) -> Result<(Vec<Spanned<Value>>, Option<Vec<VirtualKey>>), ()> {
+    let mut zone = None;
+    let mut rest = Vec::new();
+    for arg in args {
+        match &arg.0 {
+            Value::Named { name, value } if name == "zone" => zone = Some(get_zone(&value.0, zones)?),
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    Ok((rest, zone))
+}
+
+/// Removes the `delay = ...`, `phase = ...` and `repeat = ...` arguments, that every light effect can have
+fn take_timing(args: &[Spanned<Value>]) -> Result<(Vec<Spanned<Value>>, Option<Timing>), ()> {
+    let mut timing = None;
+    let mut rest = Vec::new();
+    for arg in args {
+        match &arg.0 {
+            Value::Named { name, value } if name == "delay" => timing.get_or_insert_with(Timing::default).delay = get_millis(&value.0)?,
+            Value::Named { name, value } if name == "phase" => timing.get_or_insert_with(Timing::default).phase = get_millis(&value.0)?,
+            Value::Named { name, value } if name == "repeat" => timing.get_or_insert_with(Timing::default).repeat = match &value.0 {
+                Value::Integer(times) if *times > 0 => Repeat::Times(*times as u32),
+                Value::Variable { name } if name == "forever" => Repeat::Forever,
+                _ => return Err(())
+            },
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    Ok((rest, timing))
+}
+
+/// Removes the `blend = ...` argument, that every light effect can have
+fn take_blend(args: &[Spanned<Value>]) -> Result<(Vec<Spanned<Value>>, Option<BlendMode>), ()> {
+    let mut blend = None;
+    let mut rest = Vec::new();
+    for arg in args {
+        match &arg.0 {
+            Value::Named { name, value } if name == "blend" => blend = Some(match &value.0 {
+                Value::Variable { name } if name == "normal" => BlendMode::Normal,
+                Value::Variable { name } if name == "add" => BlendMode::Add,
+                Value::Variable { name } if name == "multiply" => BlendMode::Multiply,
+                Value::Variable { name } if name == "screen" => BlendMode::Screen,
+                Value::Variable { name } if name == "overlay" => BlendMode::Overlay,
+                Value::Variable { name } if name == "max" => BlendMode::Max,
+                Value::Variable { name } if name == "min" => BlendMode::Min,
+                Value::Variable { name } if name == "replace" => BlendMode::Replace,
+                _ => return Err(())
+            }),
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    Ok((rest, blend))
+}
+
+/// The name of a zone, or a list of keys
+fn get_zone(value: &Value, zones: &HashMap<String, Vec<VirtualKey>>) -> Result<Vec<VirtualKey>, ()> {
+    match value {
+        Value::Variable { name } => zones.get(name).cloned().ok_or_else(|| eprintln!("Zone {name} doesn't exist")),
+        Value::List(keys) => keys.iter().map(|(key, _)| get_key(key)).collect(),
+        _ => Err(())
+    }
+}
+
+/// `place(target, x, y, rotation, scale)`
+fn get_placement(args: &[Spanned<Value>]) -> Result<Placement, ()> {
+    if args.len() != 5 {
+        return Err(())
+    }
+
+    let target = get_device_target(&args[0].0)?;
+    let x = match &args[1].0 {
+        Value::Float(x) => *x as f64,
+        _ => return Err(())
+    };
+    let y = match &args[2].0 {
+        Value::Float(y) => *y as f64,
+        _ => return Err(())
+    };
+    let rotation = match &args[3].0 {
+        Value::Float(f) => Deg(*f).into(),
+        _ => return Err(())
+    };
+    let scale = match &args[4].0 {
+        Value::Float(scale) => *scale as f64,
+        _ => return Err(())
+    };
+
+    Ok(Placement {
+        target,
+        offset: (x, y),
+        rotation,
+        scale,
+    })
+}
+
+/// `calibrate(device, red, green, blue)`, like `calibrate(mouse, 1.0, 0.8, 0.9)`
+fn get_calibration(args: &[Spanned<Value>]) -> Result<Calibration, ()> {
+    let (target, red, green, blue) = match args {
+        [(target, _), (red, _), (green, _), (blue, _)] => (target, red, green, blue),
+        _ => return Err(())
+    };
+    let gain = |value: &Value| match value {
+        Value::Float(gain) if *gain >= 0.0 => Ok(*gain),
+        _ => Err(())
+    };
+
+    Ok(Calibration {
+        target: get_device_target(target)?,
+        gains: (gain(red)?, gain(green)?, gain(blue)?),
     })
 }
 
+/// A device type, `all`, or the model or serial of a device (between quotes)
+fn get_device_target(value: &Value) -> Result<DeviceTarget, ()> {
+    match value {
+        Value::Variable { name } if name == "all" => Ok(DeviceTarget::All),
+        Value::Variable { name } => Ok(DeviceTarget::Type(DeviceType::try_from(name.as_str()).map_err(|_| ())?)),
+        Value::Str(name) => Ok(DeviceTarget::Name(name.clone())),
+        _ => Err(())
+    }
+}
+
+/// Between 0.0 and 1.0
+fn get_brightness(value: &Value) -> Result<f64, ()> {
+    match value {
+        Value::Float(brightness) if (0.0..=1.0).contains(brightness) => Ok(*brightness as f64),
+        _ => Err(())
+    }
+}
+
+fn get_key(value: &Value) -> Result<VirtualKey, ()> {
+    match value {
+        Value::EnumVariant { enum_name, variant } if enum_name == "Key" => variant.as_str().try_into().map_err(|_| ()),
+        _ => Err(())
+    }
+}
+
+fn get_key_color_action(args: &[Spanned<Value>]) -> Result<Action, ()> {
+    if args.len() != 2 {
+        return Err(())
+    }
+    let key = get_key(&args[0].0)?;
+    let color = match &args[1].0 {
+        Value::Color(c) => c.clone(),
+        _ => return Err(())
+    };
+
+    Ok(Action::KeyEffect(key, Box::new(Action::StaticColor(color))))
+}
+
+fn get_key_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>, zones: &HashMap<String, Vec<VirtualKey>>) -> Result<Action, ()> {
+    if args.len() != 2 {
+        return Err(())
+    }
+    let key = get_key(&args[0].0)?;
+    let effect = match &args[1].0 {
+        Value::Call { name: (name, _), args } => get_action(name, args, animations, zones)?.ok_or(())?,
+        _ => return Err(())
+    };
+    if let Action::Zone(..) = effect {
+        eprintln!("The effect of key_effect(...) is already applied to a single key, it can't have a zone");
+        return Err(())
+    }
+    if effect.is_light_effect() {
+        Ok(Action::KeyEffect(key, Box::new(effect)))
+    } else {
+        Err(()) // Only light effects can be applied to a key
+    }
+}
+
 fn get_static_color_action(args: &[Spanned<Value>]) -> Result<Action, ()> {
     if args.len() != 1 {
         return Err(())
@@ -355,7 +696,9 @@ fn get_static_color_action(args: &[Spanned<Value>]) -> Result<Action, ()> {
     }
 }
 
+/// `ripple_effect(animation, duration, speed, light_amount, center = [0.5, 0.5])`, `center` is optional
 fn get_ripple_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    let (args, center) = take_center(args)?;
     if args.len() != 4 {
         return Err(())
     }
@@ -370,7 +713,7 @@ fn get_ripple_effect_action(args: &[Spanned<Value>], animations: &HashMap<String
     };
     let animation = animations.get(animation).ok_or(())?.clone();
     let duration = match duration {
-        Value::Integer(millis) => Duration::from_millis(*millis as u64),
+        Value::Integer(millis) if *millis > 0 => Duration::from_millis(*millis as u64),
         _ => return Err(())
     };
     let speed = match speed {
@@ -387,10 +730,351 @@ fn get_ripple_effect_action(args: &[Spanned<Value>], animations: &HashMap<String
         duration,
         speed: *speed as f64,
         light_amount: *light_amount as f64,
+        center,
+    }))
+}
+
+/// `flash_effect(animation, duration)`
+fn get_flash_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    if args.len() != 2 {
+        return Err(())
+    }
+    let animation = get_animation(&args[0].0, animations)?;
+    let duration = match &args[1].0 {
+        Value::Integer(millis) if *millis > 0 => Duration::from_millis(*millis as u64),
+        _ => return Err(())
+    };
+
+    Ok(Action::FlashEffect(FlashAnimation {
+        animation,
+        duration,
+    }))
+}
+
+/// `key_ripple_effect(animation, speed, light_amount)`
+fn get_key_ripple_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    if args.len() != 3 {
+        return Err(())
+    }
+    let animation = get_animation(&args[0].0, animations)?;
+    let speed = match &args[1].0 {
+        Value::Float(speed) => speed,
+        _ => return Err(())
+    };
+    let light_amount = match &args[2].0 {
+        Value::Float(light_amount) => light_amount,
+        _ => return Err(())
+    };
+
+    Ok(Action::KeyRippleEffect(KeyRippleAnimation {
+        animation,
+        speed: *speed as f64,
+        light_amount: *light_amount as f64,
     }))
 }
 
+/// `heat_map_effect(animation, half_life, max_presses)`
+fn get_heat_map_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    if args.len() != 3 {
+        return Err(())
+    }
+    let animation = get_animation(&args[0].0, animations)?;
+    let half_life = match &args[1].0 {
+        Value::Integer(millis) if *millis > 0 => Duration::from_millis(*millis as u64),
+        _ => return Err(())
+    };
+    let max_presses = match &args[2].0 {
+        Value::Float(max_presses) if *max_presses > 0.0 => max_presses,
+        _ => return Err(())
+    };
+
+    Ok(Action::HeatMapEffect(HeatMapAnimation {
+        animation,
+        half_life,
+        max_presses: *max_presses as f64,
+    }))
+}
+
+/// `color_change_effect(animation, duration, mode = loop|ping_pong|once)`, `mode` is optional
+fn get_color_change_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    let (args, mode) = match args {
+        [args @ .., (Value::Named { name, value }, _)] if name == "mode" => {
+            let mode = match &value.0 {
+                Value::Variable { name } if name == "loop" => ColorChangeMode::Loop,
+                Value::Variable { name } if name == "ping_pong" => ColorChangeMode::PingPong,
+                Value::Variable { name } if name == "once" => ColorChangeMode::Once,
+                _ => return Err(())
+            };
+            (args, mode)
+        },
+        args => (args, ColorChangeMode::Loop),
+    };
+    if args.len() != 2 {
+        return Err(())
+    }
+    let animation = get_animation(&args[0].0, animations)?;
+    let duration = match &args[1].0 {
+        Value::Integer(millis) if *millis > 0 => Duration::from_millis(*millis as u64),
+        _ => return Err(())
+    };
+
+    Ok(Action::ColorChangeEffect(ColorChangeAnimation {
+        animation,
+        duration,
+        mode,
+    }))
+}
+
+/// `rainbow_spiral_effect(speed, twist, center = [0.5, 0.5])`, `center` is optional
+fn get_rainbow_spiral_effect_action(args: &[Spanned<Value>]) -> Result<Action, ()> {
+    let (args, center) = take_center(args)?;
+    if args.len() != 2 {
+        return Err(())
+    }
+    let speed = get_float(&args[0].0)?;
+    let twist = get_float(&args[1].0)?;
+
+    Ok(Action::RainbowSpiralEffect(RainbowSpiralAnimation {
+        speed,
+        twist,
+        center,
+    }))
+}
+
+/// `starfield_effect(color, density, twinkle, seed = 0)`, `seed` is optional
+fn get_starfield_effect_action(args: &[Spanned<Value>]) -> Result<Action, ()> {
+    let (args, seed) = match args {
+        [args @ .., (Value::Named { name, value }, _)] if name == "seed" => match value.0 {
+            Value::Integer(seed) => (args, seed as u64),
+            _ => return Err(())
+        },
+        args => (args, 0),
+    };
+    if args.len() != 3 {
+        return Err(())
+    }
+    let color = match &args[0].0 {
+        Value::Color(color) => color.clone().into(),
+        _ => return Err(())
+    };
+    let density = match args[1].0 {
+        Value::Float(density) if (0.0..=1.0).contains(&density) => density as f64,
+        _ => return Err(())
+    };
+    let twinkle = match args[2].0 {
+        Value::Integer(millis) if millis > 0 => Duration::from_millis(millis as u64),
+        _ => return Err(())
+    };
+
+    Ok(Action::StarfieldEffect(StarfieldAnimation {
+        color,
+        density,
+        twinkle,
+        seed,
+    }))
+}
+
+/// `fire_effect(animation, speed, height)`
+fn get_fire_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    if args.len() != 3 {
+        return Err(())
+    }
+    let animation = get_animation(&args[0].0, animations)?;
+    let speed = get_float(&args[1].0)?;
+    let height = match get_float(&args[2].0)? {
+        height if height > 0.0 => height,
+        _ => return Err(())
+    };
+
+    Ok(Action::FireEffect(FireAnimation {
+        animation,
+        speed,
+        height,
+    }))
+}
+
+/// `plasma_effect(animation, speed, scale)`
+fn get_plasma_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    if args.len() != 3 {
+        return Err(())
+    }
+    let animation = get_animation(&args[0].0, animations)?;
+    let speed = get_float(&args[1].0)?;
+    let scale = match get_float(&args[2].0)? {
+        scale if scale > 0.0 => scale,
+        _ => return Err(())
+    };
+
+    Ok(Action::PlasmaEffect(PlasmaAnimation {
+        animation,
+        speed,
+        scale,
+    }))
+}
+
+/// `linear_gradient(animation, angle)`
+fn get_linear_gradient_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    if args.len() != 2 {
+        return Err(())
+    }
+    let animation = get_animation(&args[0].0, animations)?;
+    let angle = match args[1].0 {
+        Value::Float(angle) => Deg(angle).into(),
+        _ => return Err(())
+    };
+
+    Ok(Action::LinearGradient(LinearGradient {
+        animation,
+        angle,
+    }))
+}
+
+/// `radial_gradient(animation, center, radius)`
+fn get_radial_gradient_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    if args.len() != 3 {
+        return Err(())
+    }
+    let animation = get_animation(&args[0].0, animations)?;
+    let center = get_device_point(&args[1].0)?;
+    let radius = match get_float(&args[2].0)? {
+        radius if radius > 0.0 => radius,
+        _ => return Err(())
+    };
+
+    Ok(Action::RadialGradient(RadialGradient {
+        animation,
+        center,
+        radius,
+    }))
+}
+
+/// `image_effect("logo.png", fit = contain)`, the fit is optional
+fn get_image_effect_action(args: &[Spanned<Value>]) -> Result<Action, ()> {
+    let (args, fit) = match args {
+        [args @ .., (Value::Named { name, value }, _)] if name == "fit" => {
+            let fit = match &value.0 {
+                Value::Variable { name } if name == "cover" => ImageFit::Cover,
+                Value::Variable { name } if name == "contain" => ImageFit::Contain,
+                Value::Variable { name } if name == "stretch" => ImageFit::Stretch,
+                _ => return Err(())
+            };
+            (args, fit)
+        },
+        args => (args, ImageFit::Contain),
+    };
+    let path = match args {
+        [(Value::Str(path), _)] => path.clone(),
+        _ => return Err(())
+    };
+
+    Ok(Action::ImageEffect(ImageAnimation {
+        path,
+        fit,
+    }))
+}
+
+/// `audio_effect(source, animation, bands, mode = bars)`, the mode is optional
+fn get_audio_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    let (args, mode) = match args {
+        [args @ .., (Value::Named { name, value }, _)] if name == "mode" => {
+            let mode = match &value.0 {
+                Value::Variable { name } if name == "bars" => AudioMode::Bars,
+                Value::Variable { name } if name == "pulse" => AudioMode::Pulse,
+                _ => return Err(())
+            };
+            (args, mode)
+        },
+        args => (args, AudioMode::Bars),
+    };
+    if args.len() != 3 {
+        return Err(())
+    }
+    let source = match &args[0].0 {
+        Value::Str(source) => source.clone(),
+        _ => return Err(())
+    };
+    let animation = get_animation(&args[1].0, animations)?;
+    let bands = match &args[2].0 {
+        Value::Integer(bands) if *bands > 0 => *bands as usize,
+        _ => return Err(())
+    };
+
+    Ok(Action::AudioEffect(AudioAnimation {
+        source,
+        animation,
+        bands,
+        mode,
+    }))
+}
+
+/// `gauge_effect(cpu|memory|temperature, animation, mode = bar)`, the mode is optional
+fn get_gauge_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    let (args, mode) = match args {
+        [args @ .., (Value::Named { name, value }, _)] if name == "mode" => {
+            let mode = match &value.0 {
+                Value::Variable { name } if name == "bar" => GaugeMode::Bar,
+                Value::Variable { name } if name == "color" => GaugeMode::Color,
+                _ => return Err(())
+            };
+            (args, mode)
+        },
+        args => (args, GaugeMode::Bar),
+    };
+    if args.len() != 2 {
+        return Err(())
+    }
+    let metric = match &args[0].0 {
+        Value::Variable { name } if name == "cpu" => Metric::Cpu,
+        Value::Variable { name } if name == "memory" => Metric::Memory,
+        Value::Variable { name } if name == "temperature" => Metric::Temperature,
+        _ => return Err(())
+    };
+    let animation = get_animation(&args[1].0, animations)?;
+
+    Ok(Action::GaugeEffect(GaugeAnimation {
+        metric,
+        animation,
+        mode,
+    }))
+}
+
+/// A key (`Key::G`), or `[x, y]` relative to the device (`[0.5, 0.5]` is the center)
+fn get_device_point(value: &Value) -> Result<DevicePoint, ()> {
+    match value {
+        Value::EnumVariant { .. } => Ok(DevicePoint::Key(get_key(value)?)),
+        Value::List(coordinates) => match coordinates.as_slice() {
+            [(x, _), (y, _)] => Ok(DevicePoint::Relative(get_float(x)?, get_float(y)?)),
+            _ => Err(())
+        },
+        _ => Err(())
+    }
+}
+
+/// The optional last `center = ...` argument, the center of the device if there is none
+fn take_center(args: &[Spanned<Value>]) -> Result<(&[Spanned<Value>], DevicePoint), ()> {
+    match args {
+        [args @ .., (Value::Named { name, value }, _)] if name == "center" => Ok((args, get_device_point(&value.0)?)),
+        args => Ok((args, DevicePoint::CENTER)),
+    }
+}
+
+fn get_float(value: &Value) -> Result<f64, ()> {
+    match value {
+        Value::Float(float) => Ok(*float as f64),
+        _ => Err(())
+    }
+}
+
+fn get_animation(value: &Value, animations: &HashMap<String, ColorAnimation>) -> Result<ColorAnimation, ()> {
+    match value {
+        Value::Variable { name } => animations.get(name).cloned().ok_or(()),
+        _ => Err(())
+    }
+}
+
+/// `wave_effect(animation, duration, speed, light_amount, rotation, two_sides, center = [0.5, 0.5])`, `center` is optional
 fn get_wave_effect_action(args: &[Spanned<Value>], animations: &HashMap<String, ColorAnimation>) -> Result<Action, ()> {
+    let (args, center) = take_center(args)?;
     if args.len() != 6 {
         return Err(())
     }
@@ -407,7 +1091,7 @@ fn get_wave_effect_action(args: &[Spanned<Value>], animations: &HashMap<String,
     };
     let animation = animations.get(animation).ok_or(())?.clone();
     let duration = match duration {
-        Value::Integer(millis) => Duration::from_millis(*millis as u64),
+        Value::Integer(millis) if *millis > 0 => Duration::from_millis(*millis as u64),
         _ => return Err(())
     };
     let speed = match speed {
@@ -434,11 +1118,17 @@ fn get_wave_effect_action(args: &[Spanned<Value>], animations: &HashMap<String,
         light_amount,
         rotation,
         two_sides,
+        center,
     }))
 }
 
+/// `switch_profile(profile, fade = 400)`, the fade is optional
 fn get_switch_profile_action(args: &[Spanned<Value>], profile_names: &[&str]) -> Result<Action, ()> {
-    if !args.len() == 1 {
+    let (args, fade) = match args {
+        [args @ .., (Value::Named { name, value }, _)] if name == "fade" => (args, Some(get_millis(&value.0)?)),
+        args => (args, None),
+    };
+    if args.len() != 1 {
         return Err(())
     }
     let profile_name = if let (Value::Variable { name }, _) = args.get(0).unwrap() {
@@ -451,7 +1141,15 @@ fn get_switch_profile_action(args: &[Spanned<Value>], profile_names: &[&str]) ->
         return Err(());
     };
 
-    Ok(Action::SwitchProfile(profile_name))
+    Ok(Action::SwitchProfile(profile_name, fade))
+}
+
+/// A duration in milliseconds, that can be 0
+fn get_millis(value: &Value) -> Result<Duration, ()> {
+    match value {
+        Value::Integer(millis) if *millis >= 0 => Ok(Duration::from_millis(*millis as u64)),
+        _ => Err(())
+    }
 }
 
 fn get_press_key_action(args: &[Spanned<Value>]) -> Result<Action, ()> {
@@ -529,13 +1227,137 @@ pub enum Action {
     },
     ReleaseKey(VirtualKey),
     PressKey(VirtualKey),
-    SwitchProfile(String),
+    /// The profile, and how long the lights fade (`None` for the fade of the settings)
+    SwitchProfile(String, Option<Duration>),
+    /// Between 0.0 and 1.0
+    SetBrightness(f64),
+    /// Adds to the brightness (or removes from it, if negative)
+    ChangeBrightness(f64),
     StaticColor(Color),
     RippleEffect(RippleAnimation),
     WaveEffect(WaveAnimation),
+    /// Reacts to key presses
+    FlashEffect(FlashAnimation),
+    /// Reacts to key presses
+    KeyRippleEffect(KeyRippleAnimation),
+    /// Reacts to key presses
+    HeatMapEffect(HeatMapAnimation),
+    ColorChangeEffect(ColorChangeAnimation),
+    RainbowSpiralEffect(RainbowSpiralAnimation),
+    StarfieldEffect(StarfieldAnimation),
+    FireEffect(FireAnimation),
+    PlasmaEffect(PlasmaAnimation),
+    LinearGradient(LinearGradient),
+    RadialGradient(RadialGradient),
+    ImageEffect(ImageAnimation),
+    AudioEffect(AudioAnimation),
+    GaugeEffect(GaugeAnimation),
+    /// A light effect that only applies to some devices
+    OnDevice(DeviceTarget, Box<Action>),
+    /// A light effect that only applies to the LED of a key
+    KeyEffect(VirtualKey, Box<Action>),
+    /// A light effect that only applies to the LEDs of some keys
+    Zone(Vec<VirtualKey>, Box<Action>),
+    /// A light effect combined with the effects below it with another mode than alpha compositing
+    Blend(BlendMode, Box<Action>),
+    /// A light effect that doesn't start with its profile, or that doesn't repeat forever
+    Timed(Timing, Box<Action>),
+}
+
+impl Action {
+    /// Effects that can be applied to a device, a zone or a key
+    pub fn is_light_effect(&self) -> bool {
+        match self {
+            Action::StaticColor(_) | Action::RippleEffect(_) | Action::WaveEffect(_) | Action::ColorChangeEffect(_) => true,
+            Action::FlashEffect(_) | Action::KeyRippleEffect(_) | Action::HeatMapEffect(_) => true,
+            Action::RainbowSpiralEffect(_) | Action::StarfieldEffect(_) | Action::FireEffect(_) | Action::PlasmaEffect(_) => true,
+            Action::LinearGradient(_) | Action::RadialGradient(_) | Action::ImageEffect(_) => true,
+            Action::AudioEffect(_) | Action::GaugeEffect(_) => true,
+            Action::Blend(_, action) | Action::Timed(_, action) => action.is_light_effect(),
+            _ => false,
+        }
+    }
 }
 
 enum MaybeExpanded {
     Expanded(Actions),
     NotExpanded(Calls)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arg(value: Value) -> Spanned<Value> {
+        (value, 0..0)
+    }
+
+    fn named(name: &str, value: Value) -> Spanned<Value> {
+        arg(Value::Named { name: name.to_string(), value: Box::new(arg(value)) })
+    }
+
+    fn parse(name: FuncName, args: Vec<Spanned<Value>>) -> Result<Option<Action>, ()> {
+        let animations = HashMap::from([("fade".to_string(), ColorAnimation { name: "fade".to_string(), keyframes: Vec::new() })]);
+        get_action(&name, &args, &animations, &zones::builtin_zones())
+    }
+
+    fn wave(duration: i32) -> Vec<Spanned<Value>> {
+        vec![
+            arg(Value::Variable { name: "fade".to_string() }),
+            arg(Value::Integer(duration)),
+            arg(Value::Float(1.0)),
+            arg(Value::Float(0.5)),
+            arg(Value::Float(0.0)),
+            arg(Value::Bool(false)),
+        ]
+    }
+
+    fn ripple(duration: i32) -> Vec<Spanned<Value>> {
+        vec![
+            arg(Value::Variable { name: "fade".to_string() }),
+            arg(Value::Integer(duration)),
+            arg(Value::Float(1.0)),
+            arg(Value::Float(0.5)),
+        ]
+    }
+
+    fn flash(duration: i32) -> Vec<Spanned<Value>> {
+        vec![
+            arg(Value::Variable { name: "fade".to_string() }),
+            arg(Value::Integer(duration)),
+        ]
+    }
+
+    #[test]
+    fn durations_must_be_positive() {
+        for duration in [0, -1000] {
+            assert!(parse(FuncName::WaveEffect, wave(duration)).is_err());
+            assert!(parse(FuncName::RippleEffect, ripple(duration)).is_err());
+            assert!(parse(FuncName::FlashEffect, flash(duration)).is_err());
+        }
+
+        assert!(matches!(parse(FuncName::WaveEffect, wave(1000)), Ok(Some(Action::WaveEffect(_)))));
+        assert!(matches!(parse(FuncName::RippleEffect, ripple(1000)), Ok(Some(Action::RippleEffect(_)))));
+        assert!(matches!(parse(FuncName::FlashEffect, flash(1000)), Ok(Some(Action::FlashEffect(_)))));
+    }
+
+    #[test]
+    fn delays_and_phases_must_not_be_negative() {
+        for name in ["delay", "phase"] {
+            let mut args = wave(1000);
+            args.push(named(name, Value::Integer(-1)));
+            assert!(parse(FuncName::WaveEffect, args).is_err());
+        }
+
+        let mut args = wave(1000);
+        args.push(named("delay", Value::Integer(0)));
+        args.push(named("phase", Value::Integer(500)));
+        match parse(FuncName::WaveEffect, args) {
+            Ok(Some(Action::Timed(timing, _))) => {
+                assert_eq!(timing.delay, Duration::ZERO);
+                assert_eq!(timing.phase, Duration::from_millis(500));
+            },
+            _ => panic!("expected a timed wave"),
+        }
+    }
+}
@@ -2,6 +2,7 @@ use std::{io::ErrorKind, ops::Range};
 
 use nom::{branch::alt, bytes::complete::{tag, take}, combinator::{eof, map, map_res}, multi::{many0, many_till, separated_list0}, sequence::{delimited, pair, preceded, separated_pair}, InputTake};
 use nom_supreme::{error::{BaseErrorKind, ErrorTree, GenericErrorTree}, ParserExt};
+use sis_core::{DeviceTarget, DeviceType};
 
 use crate::{combinators::{map_with_span, spanned}, statement::{Color, FuncName, Keyframe, Statement, Value}, token::{Keyword, Spanned, ToRange, TokError, TokResult, TokSpan, Token, Tokens}};
 
@@ -50,6 +51,21 @@ pub(crate) fn integer<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanne
     )(input)
 }
 
+pub(crate) fn string<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<String>> {
+    map_res(
+        spanned(take(1 as usize)),
+        |(consumed, span): (Tokens, Range<usize>)|{
+            match consumed.tokens[0].fragment() {
+                Token::Str{content} => Ok((String::from(*content), span)),
+                _ => Err(ErrorTree::Base {
+                    location: consumed,
+                    kind: BaseErrorKind::External(Box::new(tokio::io::Error::new(ErrorKind::Other, "Expected string")))
+                })
+            }
+        }
+    )(input)
+}
+
 pub(crate) fn boolean<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<bool>> {
     map_res(
         spanned(take(1 as usize)),
@@ -67,6 +83,13 @@ pub(crate) fn boolean<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanne
 
 fn value<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Value>> {
     alt((
+        map(
+            function_call,
+            |(call, span)| match call {
+                Statement::Call { name, args } => (Value::Call { name, args }, span),
+                _ => unreachable!(),
+            }
+        ),
         map_with_span(separated_pair(
             ident,
             pair(
@@ -95,10 +118,40 @@ fn value<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Value>> {
         map(
             boolean,
             |(s, span)| (Value::Bool(s), span)
+        ),
+        map(
+            string,
+            |(s, span)| (Value::Str(s), span)
+        ),
+        map_with_span(
+            delimited(
+                Token::Separator('['),
+                separated_list0(
+                    Token::Separator(','),
+                    value
+                ),
+                Token::Separator(']')
+            ),
+            |values, span| (Value::List(values), span)
         )
     ))(input)
 }
 
+/// A value, or a named (optional) argument like `zone = wasd`
+fn argument<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Value>> {
+    alt((
+        map_with_span(
+            separated_pair(
+                ident,
+                Token::Separator('='),
+                value
+            ),
+            |((name, _), value), span| (Value::Named { name, value: Box::new(value) }, span)
+        ),
+        value
+    ))(input)
+}
+
 pub(crate) fn func_name<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<FuncName>> {
     map(
         ident,
@@ -111,6 +164,30 @@ pub(crate) fn func_name<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Span
                 "wave_effect" => FuncName::WaveEffect,
                 "ripple_effect" => FuncName::RippleEffect,
                 "static_color" => FuncName::StaticColor,
+                "key_color" => FuncName::KeyColor,
+                "key_effect" => FuncName::KeyEffect,
+                "flash_effect" => FuncName::FlashEffect,
+                "key_ripple_effect" => FuncName::KeyRippleEffect,
+                "heat_map_effect" => FuncName::HeatMapEffect,
+                "color_change_effect" => FuncName::ColorChangeEffect,
+                "rainbow_spiral_effect" => FuncName::RainbowSpiralEffect,
+                "starfield_effect" => FuncName::StarfieldEffect,
+                "fire_effect" => FuncName::FireEffect,
+                "plasma_effect" => FuncName::PlasmaEffect,
+                "linear_gradient" => FuncName::LinearGradient,
+                "radial_gradient" => FuncName::RadialGradient,
+                "image_effect" => FuncName::ImageEffect,
+                "audio_effect" => FuncName::AudioEffect,
+                "gauge_effect" => FuncName::GaugeEffect,
+                "place" => FuncName::Place,
+                "profile_fade" => FuncName::ProfileFade,
+                "fps" => FuncName::Fps,
+                "brightness" => FuncName::Brightness,
+                "gamma" => FuncName::Gamma,
+                "calibrate" => FuncName::Calibrate,
+                "set_brightness" => FuncName::SetBrightness,
+                "brightness_up" => FuncName::BrightnessUp,
+                "brightness_down" => FuncName::BrightnessDown,
                 _ => FuncName::Other(name)
             }, span)
     })(input)
@@ -124,7 +201,7 @@ fn function_call<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Sta
                 Token::Separator('('),
                 separated_list0(
                     Token::Separator(','),
-                    value
+                    argument
                 ),
                 Token::Separator(')')
             )
@@ -157,10 +234,60 @@ fn loop_block<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Statem
     )(input)
 }
 
+/// `all`, a device type (`keyboard`, `mouse`...) or the model or serial number of a device (`"K70 RGB PRO"`)
+fn device_target<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<DeviceTarget>> {
+    alt((
+        map_res(
+            ident,
+            |(name, span)| {
+                let target = match name.as_str() {
+                    "all" => DeviceTarget::All,
+                    name => match DeviceType::try_from(name) {
+                        Ok(type_) => DeviceTarget::Type(type_),
+                        Err(_) => return Err(ErrorTree::Base {
+                            location: input.take(1),
+                            kind: BaseErrorKind::External(Box::new(tokio::io::Error::new(ErrorKind::Other, "Expected device type")))
+                        }),
+                    },
+                };
+
+                Ok((target, span))
+            }
+        ),
+        map(
+            string,
+            |(name, span)| (DeviceTarget::Name(name), span)
+        )
+    ))(input)
+}
+
+fn device_block<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Statement>> {
+    map_with_span(
+        preceded(
+            Token::K(Keyword::Device),
+            pair(
+                device_target.context("Expected device type, model or serial"),
+                delimited(
+                    tag(Token::Separator('{')).context("Missing {"),
+                    many0(statement),
+                    tag(Token::Separator('}')).context("Expected } or valid statement")
+                )
+            )
+        ),
+        |(target, body), span| {
+            (
+                Statement::Device { target, body: body.into_iter().map(|(s, _)| s).collect() },
+                span
+            )
+        }
+    )(input)
+}
+
 pub(crate) fn statement<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Statement>> {
     alt((
         function_call,
         loop_block,
+        device_block,
     ))(input)
 }
 
@@ -227,7 +354,33 @@ pub(crate) fn function_definition<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a
     )(input)
 }
 
+fn hex_color<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Color>> {
+    map_res(
+        spanned(take(1 as usize)),
+        |(consumed, span): (Tokens, Range<usize>)|{
+            match consumed.tokens[0].fragment() {
+                Token::HexColor(r, g, b, a) => {
+                    let channel = |c: &u8| (*c as i32, span.clone());
+                    Ok((Color(channel(r), channel(g), channel(b), channel(a)), span.clone()))
+                },
+                _ => Err(ErrorTree::Base {
+                    location: consumed,
+                    kind: BaseErrorKind::External(Box::new(tokio::io::Error::new(ErrorKind::Other, "Expected color")))
+                })
+            }
+        }
+    )(input)
+}
+
+/// `(r, g, b, a)` or `#rrggbb`/`#rrggbbaa`
 pub(crate) fn color<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Color>> {
+    alt((
+        tuple_color,
+        hex_color
+    ))(input)
+}
+
+fn tuple_color<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Color>> {
     map_with_span(delimited(
         Token::Separator('('),
         separated_pair(
@@ -314,12 +467,75 @@ pub(crate) fn macro_definition<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, '
     )(input)
 }
 
+pub(crate) fn desk_definition<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Statement>> {
+    map_with_span(
+        preceded(
+            Token::K(Keyword::Desk),
+            delimited(
+                tag(Token::Separator('{')).context("Missing {"),
+                many0(function_call),
+                tag(Token::Separator('}')).context("Expected } or place(...)")
+            )
+        ),
+        |body, span| (
+            Statement::Desk {
+                body: body.into_iter().map(|(s, _)| s).collect()
+            },
+            span
+        )
+    )(input)
+}
+
+/// `zone name = [Key::A, ...]`. `zone` isn't a keyword, so it can still be used for the `zone = ...` argument
+pub(crate) fn zone_definition<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Statement>> {
+    map_with_span(
+        preceded(
+            Token::Ident { name: "zone" },
+            separated_pair(
+                ident.context("Expected zone name"),
+                tag(Token::Separator('=')).context("Missing ="),
+                value.context("Expected list of keys")
+            )
+        ),
+        |((name, _), keys), span| (
+            Statement::Zone {
+                name,
+                keys
+            },
+            span
+        )
+    )(input)
+}
+
+/// `settings { ... }`. Like `zone`, `settings` isn't a keyword
+pub(crate) fn settings_definition<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Statement>> {
+    map_with_span(
+        preceded(
+            Token::Ident { name: "settings" },
+            delimited(
+                tag(Token::Separator('{')).context("Missing {"),
+                many0(function_call),
+                tag(Token::Separator('}')).context("Expected } or a setting")
+            )
+        ),
+        |body, span| (
+            Statement::Settings {
+                body: body.into_iter().map(|(s, _)| s).collect()
+            },
+            span
+        )
+    )(input)
+}
+
 fn top_level_statements<'a, 'b>(input: Tokens<'a, 'b>) -> TokResult<'a, 'b, Spanned<Statement>> {
     alt((
         function_definition,
         profile_definition,
         macro_definition,
-        color_animaiton_definition
+        color_animaiton_definition,
+        desk_definition,
+        zone_definition,
+        settings_definition
     ))(input)
 }
 
@@ -1,4 +1,4 @@
-use sis_core::{rgbau8_to_rgbaf32, RGBAf32};
+use sis_core::{rgbau8_to_rgbaf32, DeviceTarget, RGBAf32};
 
 use crate::token::Spanned;
 
@@ -12,6 +12,30 @@ pub enum FuncName {
     WaveEffect,
     RippleEffect,
     StaticColor,
+    KeyColor,
+    KeyEffect,
+    FlashEffect,
+    KeyRippleEffect,
+    HeatMapEffect,
+    ColorChangeEffect,
+    RainbowSpiralEffect,
+    StarfieldEffect,
+    FireEffect,
+    PlasmaEffect,
+    LinearGradient,
+    RadialGradient,
+    ImageEffect,
+    AudioEffect,
+    GaugeEffect,
+    Place,
+    ProfileFade,
+    Fps,
+    Brightness,
+    Gamma,
+    Calibrate,
+    SetBrightness,
+    BrightnessUp,
+    BrightnessDown,
 
     // User defined
     Other(String),
@@ -23,7 +47,11 @@ pub enum Statement {
     Call{ name: Spanned<FuncName>, args: Vec<Spanned<Value>> },
     Macro { name: String, body: Vec<Self> },
     ColorAnimation { name: String, body: Vec<Spanned<Keyframe>> },
-    Loop { body: Vec<Self> }
+    Loop { body: Vec<Self> },
+    Device { target: Spanned<DeviceTarget>, body: Vec<Self> },
+    Desk { body: Vec<Self> },
+    Zone { name: String, keys: Spanned<Value> },
+    Settings { body: Vec<Self> },
 }
 
 pub(crate) struct Keyframe {
@@ -61,11 +89,18 @@ impl Into<RGBAf32> for Color {
     }
 }
 
+#[derive(Clone)]
 pub enum Value {
     Variable{ name: String },
     EnumVariant{ enum_name: String, variant: String },
     Float(f32),
     Integer(i32),
     Color(Color),
-    Bool(bool)
+    Bool(bool),
+    Str(String),
+    /// A call used as an argument, like the effect of `key_effect(Key::Esc, static_color(...))`
+    Call{ name: Spanned<FuncName>, args: Vec<Spanned<Value>> },
+    List(Vec<Spanned<Value>>),
+    /// `name = value`, for optional arguments
+    Named{ name: String, value: Box<Spanned<Value>> },
 }
@@ -12,7 +12,10 @@ pub enum Token<'a> {
     Comment{content: &'a str},
     Float(f32),
     Integer(i32),
-    Bool(bool)
+    Bool(bool),
+    Str{content: &'a str},
+    /// `#rrggbb` or `#rrggbbaa`
+    HexColor(u8, u8, u8, u8),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,6 +25,8 @@ pub enum Keyword {
     Profile,
     ColorAnimation,
     Loop,
+    Device,
+    Desk,
 }
 
 /// Error containing a text span and an error message to display.
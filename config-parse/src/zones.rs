@@ -0,0 +1,30 @@
+use std::collections::HashMap;
+
+use sis_core::VirtualKey;
+
+/// Zones that can be used without defining them
+pub(crate) fn builtin_zones() -> HashMap<String, Vec<VirtualKey>> {
+    use VirtualKey as VK;
+    let zones: [(&str, Vec<VirtualKey>); 5] = [
+        ("function_row", vec![
+            VK::F1, VK::F2, VK::F3, VK::F4, VK::F5, VK::F6, VK::F7, VK::F8, VK::F9, VK::F10, VK::F11, VK::F12,
+        ]),
+        ("number_row", vec![
+            VK::Oem3, VK::N1, VK::N2, VK::N3, VK::N4, VK::N5, VK::N6, VK::N7, VK::N8, VK::N9, VK::N0, VK::OemMinus, VK::OemPlus,
+        ]),
+        ("numpad", vec![
+            VK::NumLock, VK::Div, VK::Mult, VK::Sub, VK::Add, VK::Dec,
+            VK::NP0, VK::NP1, VK::NP2, VK::NP3, VK::NP4, VK::NP5, VK::NP6, VK::NP7, VK::NP8, VK::NP9,
+        ]),
+        ("modifiers", vec![
+            VK::LShift, VK::RShift, VK::LControl, VK::RControl, VK::LMenu, VK::RMenu, VK::LWin, VK::RWin,
+        ]),
+        ("arrows", vec![
+            VK::ArrowUp, VK::ArrowDown, VK::ArrowLeft, VK::ArrowRight,
+        ]),
+    ];
+
+    zones.into_iter()
+        .map(|(name, keys)| (name.to_string(), keys))
+        .collect()
+}
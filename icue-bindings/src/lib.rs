@@ -3,7 +3,7 @@
 
 use std::{os::raw::{c_uint, c_void}, ptr::null_mut};
 
-use types::{CorsairAccessLevel, CorsairDataType, CorsairDeviceId, CorsairDeviceInfo, CorsairDevicePropertyId, CorsairError, CorsairKeyEventConfiguration, CorsairLedColor, CorsairLedLuid, CorsairLedPosition, CorsairProperty, CorsairPropertyFlags, CorsairSessionDetails, CorsairSessionState, CorsairSessionStateChangedHandler, CorsairVersion, IntoCE, KeyName};
+use types::{CorsairAccessLevel, CorsairDataType, CorsairDeviceId, CorsairDeviceInfo, CorsairDevicePropertyId, CorsairError, CorsairEvent, CorsairEventHandler, CorsairKeyEventConfiguration, CorsairMacroKeyId, CorsairLedColor, CorsairLedLuid, CorsairLedPosition, CorsairProperty, CorsairPropertyFlags, CorsairSessionDetails, CorsairSessionState, CorsairSessionStateChangedHandler, CorsairVersion, IntoCE, KeyName};
 
 mod sys;
 pub mod types;
@@ -134,15 +134,46 @@ pub fn CorsairGetSessionDetails() -> Result<CorsairSessionDetails, CorsairError>
 }
 
 #[must_use]
-pub fn CorsairSubscribeForEvents(
-    onEvent: sys::CorsairEventHandler,
-    context: *mut ::std::os::raw::c_void,
+pub unsafe fn CorsairSubscribeForEvents(
+    onEvent: CorsairEventHandler,
+    context: *mut c_void,
 ) -> Result<(), CorsairError> {
-    CorsairError::try_from(unsafe {
-        sys::CorsairSubscribeForEvents(onEvent, context)
-    })?;
+    EVENT_HANDLER_FUNCT = Some(onEvent);
+    sys::CorsairSubscribeForEvents(Some(event_handler), context).into_ce()
+}
 
-    Ok(())
+static mut EVENT_HANDLER_FUNCT: Option<CorsairEventHandler> = None;
+
+unsafe extern fn event_handler(
+    _context: *mut c_void,
+    event: *const sys::CorsairEvent,
+) {
+    let event = *event;
+    let event = match event.id {
+        sys::CorsairEventId_CEI_DeviceConnectionStatusChangedEvent => {
+            let data = *event.__bindgen_anon_1.deviceConnectionStatusChangedEvent;
+            CorsairEvent::DeviceConnectionStatusChanged {
+                device_id: CorsairDeviceId::from_sys(&data.deviceId),
+                is_connected: data.isConnected,
+            }
+        },
+        sys::CorsairEventId_CEI_KeyEvent => {
+            let data = *event.__bindgen_anon_1.keyEvent;
+            let key_id = match CorsairMacroKeyId::try_from(data.keyId) {
+                Ok(key_id) => key_id,
+                Err(_) => return,
+            };
+            CorsairEvent::Key {
+                device_id: CorsairDeviceId::from_sys(&data.deviceId),
+                key_id,
+                is_pressed: data.isPressed,
+            }
+        },
+        _ => return,
+    };
+    if let Some(funct) = &mut EVENT_HANDLER_FUNCT {
+        funct(event)
+    }
 }
 
 #[must_use]
@@ -272,15 +303,17 @@ pub fn CorsairSetLayerPriority(priority: ::std::os::raw::c_uint) -> Result<(), C
 pub fn CorsairGetLedLuidForKeyName(
     device_id: &CorsairDeviceId,
     key_name: KeyName,
-    led_id: CorsairLedLuid,
-) -> Result<(), CorsairError> {
+) -> Result<CorsairLedLuid, CorsairError> {
+    let mut led_id: sys::CorsairLedLuid = 0;
     CorsairError::try_from(unsafe {
         sys::CorsairGetLedLuidForKeyName(
             device_id.get_ptr(),
             key_name.0,
-            (&mut led_id.0.into()) as *mut sys::CorsairLedLuid
+            &mut led_id as *mut sys::CorsairLedLuid
         )
-    })
+    })?;
+
+    Ok(CorsairLedLuid(led_id))
 }
 
 #[must_use]
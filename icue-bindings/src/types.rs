@@ -2,18 +2,41 @@ use std::ffi::CString;
 
 use crate::sys;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CorsairDeviceId(CString);
 
 impl CorsairDeviceId {
+    /// Used by fake sessions, that don't get their ids from the SDK. Panics if `id` contains a nul byte
+    pub fn new(id: &str) -> CorsairDeviceId {
+        CorsairDeviceId(CString::new(id).unwrap())
+    }
+
     pub(crate) fn get_ptr(&self) -> *mut i8 {
         self.0.as_ptr() as *mut i8
     }
+
+    pub(crate) fn from_sys(id: &sys::CorsairDeviceId) -> CorsairDeviceId {
+        let id = unsafe {
+            std::ffi::CStr::from_ptr(id.as_ptr())
+        };
+        CorsairDeviceId(id.to_owned())
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CorsairLedLuid(pub(crate) u32);
 
+impl CorsairLedLuid {
+    /// Used by lighting backends that don't go through the SDK but share its LED model
+    pub fn new(id: u32) -> CorsairLedLuid {
+        CorsairLedLuid(id)
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+}
+
 impl From<CorsairLedLuid> for sys::CorsairLedLuid {
     fn from(value: CorsairLedLuid) -> Self {
         value.into()
@@ -86,6 +109,7 @@ pub enum CorsairEventId {
 }
 
 #[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CorsairMacroKeyId {
     Invalid = sys::CorsairMacroKeyId_CMKI_Invalid,
     CMKI1 = sys::CorsairMacroKeyId_CMKI_1,
@@ -190,6 +214,22 @@ bitflags::bitflags! {
 
 pub type CorsairSessionStateChangedHandler = Box<dyn Fn(CorsairSessionState, CorsairSessionDetails) -> ()>;
 
+pub type CorsairEventHandler = Box<dyn Fn(CorsairEvent) -> ()>;
+
+#[derive(Debug)]
+pub enum CorsairEvent {
+    DeviceConnectionStatusChanged {
+        device_id: CorsairDeviceId,
+        is_connected: bool,
+    },
+    /// Only sent for macro keys (like G keys)
+    Key {
+        device_id: CorsairDeviceId,
+        key_id: CorsairMacroKeyId,
+        is_pressed: bool,
+    },
+}
+
 pub struct CorsairSessionDetails {
     pub client_version: CorsairVersion,
     pub server_version: CorsairVersion,
@@ -217,8 +257,9 @@ impl From<CorsairSessionDetails> for sys::CorsairSessionDetails {
 }
 
 pub struct CorsairKeyEventConfiguration {
-    key_id: CorsairMacroKeyId,
-    is_intercepted: bool,
+    pub key_id: CorsairMacroKeyId,
+    /// When true, the key press isn't passed on to the other programs
+    pub is_intercepted: bool,
 }
 
 impl From<CorsairKeyEventConfiguration> for sys::CorsairKeyEventConfiguration {
@@ -438,13 +479,9 @@ impl From<sys::CorsairDeviceInfo> for CorsairDeviceInfo {
             std::ffi::CStr::from_ptr(value.serial.as_ptr())
         };
         let serial = serial.to_owned();
-        let id = unsafe {
-            std::ffi::CStr::from_ptr(value.id.as_ptr())
-        };
-        let id = id.to_owned();
         CorsairDeviceInfo {
             type_: value.type_.into(),
-            id: CorsairDeviceId(id),
+            id: CorsairDeviceId::from_sys(&value.id),
             serial,
             model,
             ledCount: value.ledCount,
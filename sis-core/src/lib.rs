@@ -4,7 +4,7 @@ use cgmath::Rad;
 use windows::Win32::UI::Input::KeyboardAndMouse::*;
 
 #[repr(u16)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VirtualKey {
     // https://learn.microsoft.com/en-us/windows/win32/inputdev/virtual-key-codes
     MLButton = VK_LBUTTON.0,
@@ -348,6 +348,148 @@ impl TryFrom<&str> for VirtualKey {
             "f22" => VK::F22,
             "f23" => VK::F23,
             "f24" => VK::F24,
+            "esc" => VK::Esc,
+            "tab" => VK::Tab,
+            "capslock" => VK::CapsLock,
+            "space" => VK::Space,
+            "enter" => VK::Return,
+            "backspace" => VK::Backspace,
+            "insert" => VK::Insert,
+            "delete" => VK::Delete,
+            "home" => VK::Home,
+            "end" => VK::End,
+            "pageup" => VK::PageUp,
+            "pagedown" => VK::PageDown,
+            "arrowup" => VK::ArrowUp,
+            "arrowdown" => VK::ArrowDown,
+            "arrowleft" => VK::ArrowLeft,
+            "arrowright" => VK::ArrowRight,
+            "printscreen" => VK::PrintScreen,
+            "scrolllock" => VK::Scroll,
+            "pause" => VK::Pause,
+            "numlock" => VK::NumLock,
+            "np0" => VK::NP0,
+            "np1" => VK::NP1,
+            "np2" => VK::NP2,
+            "np3" => VK::NP3,
+            "np4" => VK::NP4,
+            "np5" => VK::NP5,
+            "np6" => VK::NP6,
+            "np7" => VK::NP7,
+            "np8" => VK::NP8,
+            "np9" => VK::NP9,
+            "npadd" => VK::Add,
+            "npsub" => VK::Sub,
+            "npmult" => VK::Mult,
+            "npdiv" => VK::Div,
+            "npdec" => VK::Dec,
+            "lshift" => VK::LShift,
+            "rshift" => VK::RShift,
+            "lctrl" => VK::LControl,
+            "rctrl" => VK::RControl,
+            "lalt" => VK::LMenu,
+            "ralt" => VK::RMenu,
+            "lwin" => VK::LWin,
+            "rwin" => VK::RWin,
+            "apps" => VK::Apps,
+            "grave" => VK::Oem3,
+            "minus" => VK::OemMinus,
+            "equals" => VK::OemPlus,
+            "bracketleft" => VK::Oem4,
+            "bracketright" => VK::Oem6,
+            "backslash" => VK::Oem5,
+            "semicolon" => VK::Oem1,
+            "apostrophe" => VK::Oem7,
+            "comma" => VK::OemComma,
+            "period" => VK::OemPeriod,
+            "slash" => VK::Oem2,
+            _ => return Err(ParseError),
+        })
+    }
+}
+
+/// The devices a light effect is applied to
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceTarget {
+    All,
+    Type(DeviceType),
+    /// Model or serial number of the device
+    Name(String),
+}
+
+/// Where a device is on the desk. Devices are scaled, then rotated (clockwise) around their top left corner,
+/// and then moved by `offset` (in mm)
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub target: DeviceTarget,
+    pub offset: (f64, f64),
+    pub rotation: Rad<f32>,
+    pub scale: f64,
+}
+
+/// Multiplies the red, green and blue of a device, so it shows the same colors as the others
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    pub target: DeviceTarget,
+    pub gains: (f32, f32, f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Keyboard,
+    Mouse,
+    Mousemat,
+    Headset,
+    HeadsetStand,
+    FanLedController,
+    LedController,
+    MemoryModule,
+    Cooler,
+    Motherboard,
+    GraphicsCard,
+    Touchbar,
+    GameController,
+}
+
+impl DeviceType {
+    /// The name used in config files
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceType::Keyboard => "keyboard",
+            DeviceType::Mouse => "mouse",
+            DeviceType::Mousemat => "mousemat",
+            DeviceType::Headset => "headset",
+            DeviceType::HeadsetStand => "headset_stand",
+            DeviceType::FanLedController => "fan_led_controller",
+            DeviceType::LedController => "led_controller",
+            DeviceType::MemoryModule => "memory_module",
+            DeviceType::Cooler => "cooler",
+            DeviceType::Motherboard => "motherboard",
+            DeviceType::GraphicsCard => "graphics_card",
+            DeviceType::Touchbar => "touchbar",
+            DeviceType::GameController => "game_controller",
+        }
+    }
+}
+
+impl TryFrom<&str> for DeviceType {
+    type Error = ParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(match value {
+            "keyboard" => DeviceType::Keyboard,
+            "mouse" => DeviceType::Mouse,
+            "mousemat" => DeviceType::Mousemat,
+            "headset" => DeviceType::Headset,
+            "headset_stand" => DeviceType::HeadsetStand,
+            "fan_led_controller" => DeviceType::FanLedController,
+            "led_controller" => DeviceType::LedController,
+            "memory_module" => DeviceType::MemoryModule,
+            "cooler" => DeviceType::Cooler,
+            "motherboard" => DeviceType::Motherboard,
+            "graphics_card" => DeviceType::GraphicsCard,
+            "touchbar" => DeviceType::Touchbar,
+            "game_controller" => DeviceType::GameController,
             _ => return Err(ParseError),
         })
     }
@@ -358,7 +500,9 @@ pub struct RippleAnimation {
     pub animation: ColorAnimation,
     pub duration: Duration,
     pub speed: f64,
-    pub light_amount: f64
+    pub light_amount: f64,
+    /// Where the ripple starts from
+    pub center: DevicePoint,
 }
 
 
@@ -370,12 +514,222 @@ pub struct WaveAnimation {
     pub rotation: Rad<f32>,
     pub light_amount: f64,
     pub two_sides: bool,
+    /// Where a two sided wave starts from, one sided waves start from the edge of the device
+    pub center: DevicePoint,
+}
+
+/// Plays the animation on the key that was pressed
+#[derive(Debug, Clone)]
+pub struct FlashAnimation {
+    pub animation: ColorAnimation,
+    pub duration: Duration,
+}
+
+/// A single ripple that starts at the key that was pressed
+#[derive(Debug, Clone)]
+pub struct KeyRippleAnimation {
+    pub animation: ColorAnimation,
+    pub speed: f64,
+    pub light_amount: f64,
+}
+
+/// Keys get hotter each time they are pressed, and cool down over time
+#[derive(Debug, Clone)]
+pub struct HeatMapAnimation {
+    pub animation: ColorAnimation,
+    /// Time it takes for the heat of a key to halve
+    pub half_life: Duration,
+    /// Presses (in a short time) needed to reach the end of the animation
+    pub max_presses: f64,
+}
+
+/// When an effect plays, counted from when its profile starts
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Timing {
+    /// Nothing is shown before the delay
+    pub delay: Duration,
+    /// The effect starts this far into its animation
+    pub phase: Duration,
+    pub repeat: Repeat,
+}
+
+/// How many times an effect with a duration (like a wave) plays
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Repeat {
+    /// The effect is removed after playing this many times
+    Times(u32),
+    #[default]
+    Forever,
+}
+
+/// How the color of an effect is combined with the color of the effects below it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BlendMode {
+    /// Covers the effects below, as much as the effect is opaque
+    #[default]
+    Normal,
+    Add,
+    Multiply,
+    Screen,
+    Overlay,
+    Max,
+    Min,
+    /// Ignores the effects below, even where the effect is transparent
+    Replace,
+}
+
+/// A point of a device, wherever it is on the desk
+#[derive(Debug, Clone, PartialEq)]
+pub enum DevicePoint {
+    /// (0.0, 0.0) is the top left LED of the device and (1.0, 1.0) the bottom right one
+    Relative(f64, f64),
+    /// The LED of a key, devices without the key use their center
+    Key(VirtualKey),
+}
+
+impl DevicePoint {
+    pub const CENTER: DevicePoint = DevicePoint::Relative(0.5, 0.5);
+}
+
+/// Samples the animation from one side of the device (start) to the other (end)
+#[derive(Debug, Clone)]
+pub struct LinearGradient {
+    pub animation: ColorAnimation,
+    /// 0 goes from left to right, 90 degrees from top to bottom
+    pub angle: Rad<f32>,
+}
+
+/// Samples the animation from the center (start) outwards (end)
+#[derive(Debug, Clone)]
+pub struct RadialGradient {
+    pub animation: ColorAnimation,
+    pub center: DevicePoint,
+    /// In keys, LEDs further away have the color of the end of the animation
+    pub radius: f64,
+}
+
+/// A PNG or an animated GIF, fitted to the device
+#[derive(Debug, Clone)]
+pub struct ImageAnimation {
+    pub path: String,
+    pub fit: ImageFit,
+}
+
+/// How an image is fitted to a device that doesn't have the same shape
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFit {
+    /// Fills the whole device, the sides of the image that don't fit are cut
+    Cover,
+    /// The whole image is visible, the device isn't lit around it
+    Contain,
+    /// Fills the whole device with the whole image, without keeping its proportions
+    Stretch,
+}
+
+/// Reacts to the sound coming from `source`
+#[derive(Debug, Clone)]
+pub struct AudioAnimation {
+    /// A WAV file, `-` for stdin, or a named pipe. Stdin and pipes carry raw 16 bit mono samples at 44100 Hz
+    pub source: String,
+    pub animation: ColorAnimation,
+    /// How many frequency bands the sound is split into
+    pub bands: usize,
+    pub mode: AudioMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioMode {
+    /// One bar per band, from the lowest frequencies on the left. The animation colors the bars from bottom to top
+    Bars,
+    /// The whole device takes the color of the animation at the level of the sound
+    Pulse,
+}
+
+/// Shows how loaded the computer is
+#[derive(Debug, Clone)]
+pub struct GaugeAnimation {
+    pub metric: Metric,
+    pub animation: ColorAnimation,
+    pub mode: GaugeMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// Part of the time the CPUs are busy
+    Cpu,
+    /// Part of the memory that is used
+    Memory,
+    /// The hottest temperature sensor
+    Temperature,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GaugeMode {
+    /// Fills the LEDs from left to right, colored by the animation from its start (left) to its end (right)
+    Bar,
+    /// Every LED has the color of the animation at the current load
+    Color,
+}
+
+/// Every hue of the rainbow, turning around the center of the device
+#[derive(Debug, Clone)]
+pub struct RainbowSpiralAnimation {
+    /// Turns per second
+    pub speed: f64,
+    /// How much the hue changes from the center outwards, in turns per key
+    pub twist: f64,
+    pub center: DevicePoint,
+}
+
+/// LEDs that randomly light up and fade out
+#[derive(Debug, Clone)]
+pub struct StarfieldAnimation {
+    pub color: RGBAf32,
+    /// Part of the LEDs (between 0.0 and 1.0) that are lit at the same time
+    pub density: f64,
+    /// How long a single star lasts
+    pub twinkle: Duration,
+    /// Different seeds give different stars, the same seed always gives the same ones
+    pub seed: u64,
+}
+
+/// Flames rising from the bottom of the device
+#[derive(Debug, Clone)]
+pub struct FireAnimation {
+    /// From the coldest (start) to the hottest (end) color
+    pub animation: ColorAnimation,
+    /// In keys per second
+    pub speed: f64,
+    /// In keys
+    pub height: f64,
+}
+
+/// Colors flowing like a liquid, from smooth noise
+#[derive(Debug, Clone)]
+pub struct PlasmaAnimation {
+    pub animation: ColorAnimation,
+    /// How fast the colors change
+    pub speed: f64,
+    /// Size of the blobs of color, in keys
+    pub scale: f64,
 }
 
 #[derive(Debug, Clone)]
 pub struct ColorChangeAnimation {
     pub animation: ColorAnimation,
     pub duration: Duration,
+    pub mode: ColorChangeMode,
+}
+
+/// What a color change does after playing the animation once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChangeMode {
+    /// Starts again from the beginning
+    Loop,
+    /// Plays the animation backwards, then forwards again...
+    PingPong,
+    /// Stays at the end of the animation
+    Once,
 }
 
 
@@ -0,0 +1,143 @@
+//! Per frame cost of the compositor. `cargo bench -p sis-remapper`
+//!
+//! Rendering mustn't allocate once the buffers of the compositor have their size, which is checked before measuring
+
+use std::{alloc::{GlobalAlloc, Layout as AllocLayout, System}, hint::black_box, sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+
+use cgmath::Rad;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use icue_bindings::types::{CorsairLedLuid, CorsairLedPosition};
+use sis_core::{BlendMode, ColorAnimation, DevicePoint, DeviceTarget, Keyframe, PlasmaAnimation, RippleAnimation, Timing, VirtualKey, WaveAnimation};
+use sis_remapper::corsair::{compositor::Compositor, effects::Effect, frame::{DeviceInfo, EffectStack, Layer, PressedKey}};
+
+/// Distance between 2 neighbouring LEDs, in mm
+const LED_PITCH: f64 = 19.05;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: AllocLayout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: AllocLayout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: AllocLayout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A grid of `columns` x `rows` LEDs. The first LED is the key W, so key effects and presses have a key to use
+fn grid(columns: u32, rows: u32) -> (DeviceInfo, Vec<CorsairLedPosition>) {
+    let positions = (0..columns * rows)
+        .map(|index| CorsairLedPosition {
+            id: CorsairLedLuid::new(index),
+            cx: (index % columns) as f64 * LED_PITCH,
+            cy: (index / columns) as f64 * LED_PITCH,
+        }).collect();
+    let info = DeviceInfo {
+        type_: None,
+        model: format!("{columns}x{rows} grid"),
+        serial: String::new(),
+        keys: vec![(VirtualKey::W, CorsairLedLuid::new(0))],
+    };
+
+    (info, positions)
+}
+
+fn red_and_blue() -> ColorAnimation {
+    ColorAnimation {
+        name: "red_and_blue".into(),
+        keyframes: vec![
+            Keyframe { timestamp: 0.0, color: (1.0, 0.0, 0.0, 1.0) },
+            Keyframe { timestamp: 0.5, color: (0.0, 0.0, 1.0, 1.0) },
+            Keyframe { timestamp: 1.0, color: (1.0, 0.0, 0.0, 1.0) },
+        ],
+    }
+}
+
+fn layer(effect: Effect, blend: BlendMode, zone: Option<Vec<VirtualKey>>) -> Layer {
+    Layer {
+        target: DeviceTarget::All,
+        zone,
+        blend,
+        timing: Timing::default(),
+        start: Duration::ZERO,
+        effect,
+    }
+}
+
+/// A base color, a wave, a ripple added on top, plasma on a zone and a key effect, with a few key presses
+fn effects() -> EffectStack {
+    let mut effects = EffectStack::new();
+    effects.add_effect(layer(Effect::Static((0.1, 0.1, 0.1, 1.0)), BlendMode::Normal, None));
+    effects.add_effect(layer(Effect::Wave(WaveAnimation {
+        animation: red_and_blue(),
+        duration: Duration::from_secs(3),
+        speed: 10.0,
+        rotation: Rad(0.3),
+        light_amount: 5.0,
+        two_sides: false,
+        center: DevicePoint::CENTER,
+    }), BlendMode::Normal, None));
+    effects.add_effect(layer(Effect::Ripple(RippleAnimation {
+        animation: red_and_blue(),
+        duration: Duration::from_millis(2500),
+        speed: 5.0,
+        light_amount: 10.0,
+        center: DevicePoint::CENTER,
+    }), BlendMode::Add, None));
+    effects.add_effect(layer(Effect::Plasma(PlasmaAnimation {
+        animation: red_and_blue(),
+        speed: 1.0,
+        scale: 4.0,
+    }), BlendMode::Screen, Some(vec![VirtualKey::W])));
+    effects.add_key_effect(VirtualKey::W, layer(Effect::Static((1.0, 1.0, 1.0, 0.5)), BlendMode::Normal, None));
+    for press in 0..5 {
+        effects.press_key(PressedKey::Key(VirtualKey::W), Duration::from_millis(press * 100))
+    }
+
+    effects
+}
+
+fn render(c: &mut Criterion) {
+    let effects = effects();
+    let mut group = c.benchmark_group("render");
+    for (columns, rows) in [(10, 10), (40, 25)] {
+        let (info, positions) = grid(columns, rows);
+        let led_count = positions.len();
+        let mut compositor = Compositor::new(&info, positions);
+        // The first frame computes the geometry of the effects and gives the buffers their size.
+        // Every press happened before it, the buffer of the presses only grows when there are more of them
+        let start = Duration::from_secs(1);
+        compositor.render(&info, &effects, start);
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for frame in 1..100 {
+            black_box(compositor.render(&info, &effects, start + Duration::from_millis(frame * 16)));
+        }
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        assert_eq!(allocations, 0, "Rendering {led_count} LEDs allocated {allocations} times in 99 frames");
+
+        let mut t = start;
+        group.bench_with_input(BenchmarkId::new("leds", led_count), &led_count, |b, _| {
+            b.iter(|| {
+                t += Duration::from_millis(16);
+                black_box(compositor.render(&info, &effects, t));
+            })
+        });
+    }
+    group.finish()
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
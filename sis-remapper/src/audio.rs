@@ -0,0 +1,353 @@
+//! Sound from a file or a live stream, split into frequency bands for the light effects
+
+use std::{collections::VecDeque, f32::consts::PI, fmt::Debug, fs::File, io::{BufReader, ErrorKind, Read}, path::Path, sync::{Arc, Mutex}, time::Duration};
+
+/// Samples per analysis, about 23ms at 44100 Hz. It must be a power of two
+const WINDOW: usize = 1024;
+/// Sample rate of the raw streams (stdin and named pipes)
+const STREAM_SAMPLE_RATE: u32 = 44100;
+/// Lowest frequency of the first band
+const MIN_FREQUENCY: f32 = 40.0;
+/// Highest frequency of the last band, if the sample rate allows it
+const MAX_FREQUENCY: f32 = 16000.0;
+/// Bands this quiet (or quieter) have a level of 0.0
+const SILENCE_DB: f32 = -60.0;
+
+/// Streams are opened once and shared, so switching profiles doesn't start another reader
+static STREAMS: Mutex<Vec<(String, Arc<Stream>)>> = Mutex::new(Vec::new());
+
+pub(crate) trait AudioSource: Debug + Send + Sync {
+    /// Fills `samples` with the last samples played `t` after the effect started (mono, between -1.0 and 1.0)
+    fn window(&self, t: Duration, samples: &mut [f32]);
+    fn sample_rate(&self) -> u32;
+}
+
+/// Opens a WAV file (if `source` ends with `.wav`), stdin (`-`) or a named pipe
+pub(crate) fn open_source(source: &str) -> std::io::Result<Arc<dyn AudioSource>> {
+    if source.to_ascii_lowercase().ends_with(".wav") {
+        return Ok(Arc::new(WavFile::load(Path::new(source))?))
+    }
+
+    let mut streams = STREAMS.lock().unwrap();
+    if let Some((_, stream)) = streams.iter().find(|(name, _)| name == source) {
+        return Ok(stream.clone())
+    }
+    let stream = if source == "-" {
+        Stream::spawn(source.to_string(), || Ok(std::io::stdin()))
+    } else {
+        let path = source.to_string();
+        // Opening a pipe waits for a writer, so it is done by the reader thread
+        Stream::spawn(source.to_string(), move || File::open(path))
+    };
+    streams.push((source.to_string(), stream.clone()));
+
+    Ok(stream)
+}
+
+/// Fills `levels` with the level of each band (between 0.0 and 1.0), from the lowest frequencies to the highest
+pub(crate) fn band_levels(source: &dyn AudioSource, t: Duration, bands: usize, levels: &mut Vec<f32>) {
+    let mut samples = [0.0; WINDOW];
+    source.window(t, &mut samples);
+    let spectrum = spectrum(&samples);
+    let sample_rate = source.sample_rate() as f32;
+    let bin_width = sample_rate / WINDOW as f32;
+    let max_frequency = MAX_FREQUENCY.min(sample_rate / 2.0);
+
+    levels.clear();
+    levels.extend((0..bands).map(|band| {
+        // Bands are spaced logarithmically, like notes
+        let low = MIN_FREQUENCY * (max_frequency / MIN_FREQUENCY).powf(band as f32 / bands as f32);
+        let high = MIN_FREQUENCY * (max_frequency / MIN_FREQUENCY).powf((band + 1) as f32 / bands as f32);
+        let last = ((high / bin_width) as usize).min(spectrum.len());
+        let first = ((low / bin_width) as usize).min(last.saturating_sub(1));
+        let amplitude = spectrum[first..last].iter().copied().fold(0.0, f32::max);
+        let db = 20.0 * amplitude.max(f32::MIN_POSITIVE).log10();
+        ((db - SILENCE_DB) / -SILENCE_DB).clamp(0.0, 1.0)
+    }))
+}
+
+/// The amplitude of each frequency (up to half the sample rate), a full scale sine gives 1.0
+fn spectrum(samples: &[f32; WINDOW]) -> [f32; WINDOW / 2] {
+    let mut re = [0.0; WINDOW];
+    let mut im = [0.0; WINDOW];
+    for (i, sample) in samples.iter().enumerate() {
+        // Hann window, so sounds between two frequencies don't leak into every band
+        let hann = 0.5 - 0.5 * (2.0 * PI * i as f32 / WINDOW as f32).cos();
+        re[i] = sample * hann;
+    }
+    fft(&mut re, &mut im);
+
+    // The Hann window halves the amplitude, and it is split between the positive and negative frequencies
+    std::array::from_fn(|i| (re[i] * re[i] + im[i] * im[i]).sqrt() * 4.0 / WINDOW as f32)
+}
+
+/// In place radix-2 FFT, the length must be a power of two
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let (odd_re, odd_im) = (re[b] * cos - im[b] * sin, re[b] * sin + im[b] * cos);
+                re[b] = re[a] - odd_re;
+                im[b] = im[a] - odd_im;
+                re[a] += odd_re;
+                im[a] += odd_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// A whole WAV file, played in a loop. The channels are mixed together
+#[derive(Debug)]
+pub(crate) struct WavFile {
+    samples: Vec<f32>,
+    sample_rate: u32,
+}
+
+impl WavFile {
+    pub(crate) fn load(path: &Path) -> std::io::Result<WavFile> {
+        WavFile::parse(&std::fs::read(path)?)
+    }
+
+    fn parse(bytes: &[u8]) -> std::io::Result<WavFile> {
+        let invalid = |message: &str| std::io::Error::new(ErrorKind::InvalidData, message.to_string());
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(invalid("not a WAV file"))
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut chunks = &bytes[12..];
+        while chunks.len() >= 8 {
+            let size = u32::from_le_bytes([chunks[4], chunks[5], chunks[6], chunks[7]]) as usize;
+            let body = &chunks[8..(8 + size).min(chunks.len())];
+            match &chunks[0..4] {
+                b"fmt " => format = Some(body),
+                b"data" => data = Some(body),
+                _ => (),
+            }
+            // Chunks are padded to an even size
+            chunks = &chunks[(8 + size + size % 2).min(chunks.len())..];
+        }
+        let (format, data) = match (format, data) {
+            (Some(format), Some(data)) if format.len() >= 16 => (format, data),
+            _ => return Err(invalid("missing format or data")),
+        };
+
+        let mut tag = u16::from_le_bytes([format[0], format[1]]);
+        let channels = u16::from_le_bytes([format[2], format[3]]) as usize;
+        let sample_rate = u32::from_le_bytes([format[4], format[5], format[6], format[7]]);
+        let bits = u16::from_le_bytes([format[14], format[15]]);
+        // WAVE_FORMAT_EXTENSIBLE, the real format is in the sub format
+        if tag == 0xfffe && format.len() >= 26 {
+            tag = u16::from_le_bytes([format[24], format[25]]);
+        }
+        if channels == 0 || sample_rate == 0 {
+            return Err(invalid("no channels"))
+        }
+        let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+            (1, 8) => |b: &[u8]| (b[0] as f32 - 128.0) / 128.0,
+            (1, 16) => |b: &[u8]| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            (1, 24) => |b: &[u8]| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0,
+            (1, 32) => |b: &[u8]| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+            (3, 32) => |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            _ => return Err(invalid("unsupported sample format")),
+        };
+
+        let sample_size = bits as usize / 8;
+        let samples = data.chunks_exact(sample_size * channels)
+            .map(|frame| frame.chunks_exact(sample_size).map(decode).sum::<f32>() / channels as f32)
+            .collect();
+
+        Ok(WavFile {
+            samples,
+            sample_rate,
+        })
+    }
+}
+
+impl AudioSource for WavFile {
+    fn window(&self, t: Duration, samples: &mut [f32]) {
+        let end = (t.as_secs_f64() * self.sample_rate as f64) as i64;
+        let start = end - samples.len() as i64;
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let index = start + i as i64;
+            *sample = if index < 0 || self.samples.is_empty() {
+                0.0 // Before the start of the effect
+            } else {
+                self.samples[index as usize % self.samples.len()]
+            };
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+/// Raw 16 bit little endian mono samples, read as they come. Only the last samples are kept
+#[derive(Debug, Default)]
+struct Stream {
+    samples: Mutex<VecDeque<f32>>,
+}
+
+impl Stream {
+    fn spawn<R: Read>(name: String, open: impl FnOnce() -> std::io::Result<R> + Send + 'static) -> Arc<Stream> {
+        let stream = Arc::new(Stream::default());
+        let thread_stream = stream.clone();
+        std::thread::spawn(move || {
+            let mut reader = match open() {
+                Ok(reader) => BufReader::new(reader),
+                Err(err) => {
+                    eprintln!("Couldn't open the audio source {name}: {err}");
+                    return
+                },
+            };
+            let mut bytes = [0; 256];
+            while reader.read_exact(&mut bytes).is_ok() {
+                let mut samples = thread_stream.samples.lock().unwrap();
+                for sample in bytes.chunks_exact(2) {
+                    if samples.len() == WINDOW {
+                        samples.pop_front();
+                    }
+                    samples.push_back(i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0);
+                }
+            }
+            eprintln!("The audio source {name} was closed");
+        });
+
+        stream
+    }
+}
+
+impl AudioSource for Stream {
+    /// Always the last samples that were read, whatever `t` is
+    fn window(&self, _t: Duration, samples: &mut [f32]) {
+        let stream_samples = self.samples.lock().unwrap();
+        let missing = samples.len().saturating_sub(stream_samples.len());
+        samples[..missing].fill(0.0);
+        let skipped = stream_samples.len().saturating_sub(samples.len());
+        for (sample, stream_sample) in samples[missing..].iter_mut().zip(stream_samples.iter().skip(skipped)) {
+            *sample = *stream_sample;
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        STREAM_SAMPLE_RATE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 16 bit mono WAV file
+    fn wav(sample_rate: u32, samples: &[i16]) -> Vec<u8> {
+        let data_size = samples.len() as u32 * 2;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // Channels
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // Bytes per second
+        bytes.extend_from_slice(&2u16.to_le_bytes()); // Bytes per frame
+        bytes.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_size.to_le_bytes());
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes())
+        }
+
+        bytes
+    }
+
+    /// One second of a sine at `frequency`
+    fn sine(sample_rate: u32, frequency: f32, amplitude: f32) -> Vec<i16> {
+        (0..sample_rate)
+            .map(|i| ((2.0 * PI * frequency * i as f32 / sample_rate as f32).sin() * amplitude * 32767.0) as i16)
+            .collect()
+    }
+
+    #[test]
+    fn parses_16_bit_mono() {
+        let wav = WavFile::parse(&wav(8000, &[0, 16384, -16384, i16::MIN])).unwrap();
+        assert_eq!(wav.sample_rate, 8000);
+        assert_eq!(wav.samples, [0.0, 0.5, -0.5, -1.0]);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let valid = wav(8000, &[0, 1, 2, 3]);
+        let invalid = |bytes: &[u8]| WavFile::parse(bytes).map(|_| ()).unwrap_err().kind();
+
+        assert_eq!(invalid(&valid[..8]), ErrorKind::InvalidData);
+        let mut not_riff = valid.clone();
+        not_riff[0..4].copy_from_slice(b"RIFX");
+        assert_eq!(invalid(&not_riff), ErrorKind::InvalidData);
+        let mut not_wave = valid.clone();
+        not_wave[8..12].copy_from_slice(b"AVI ");
+        assert_eq!(invalid(&not_wave), ErrorKind::InvalidData);
+        // Without the data chunk
+        assert_eq!(invalid(&valid[..36]), ErrorKind::InvalidData);
+        let mut no_channels = valid.clone();
+        no_channels[22..24].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(invalid(&no_channels), ErrorKind::InvalidData);
+        let mut compressed = valid.clone();
+        compressed[20..22].copy_from_slice(&2u16.to_le_bytes()); // ADPCM
+        assert_eq!(invalid(&compressed), ErrorKind::InvalidData);
+        let mut twelve_bits = valid;
+        twelve_bits[34..36].copy_from_slice(&12u16.to_le_bytes());
+        assert_eq!(invalid(&twelve_bits), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn sine_is_in_its_band() {
+        // With 8 bands from 40Hz to 16kHz, 1kHz is in the 5th one (800Hz to 1692Hz)
+        let wav = WavFile::parse(&wav(44100, &sine(44100, 1000.0, 0.8))).unwrap();
+        let mut levels = Vec::new();
+        band_levels(&wav, Duration::from_millis(500), 8, &mut levels);
+
+        assert_eq!(levels.len(), 8);
+        let loudest = (0..8).max_by(|a, b| levels[*a].total_cmp(&levels[*b])).unwrap();
+        assert_eq!(loudest, 4, "{levels:?}");
+        assert!(levels[4] > 0.9, "{levels:?}");
+        for (band, level) in levels.iter().enumerate().filter(|(band, _)| *band != 4) {
+            assert!(*level < levels[4] / 2.0, "Band {band} is too loud: {levels:?}")
+        }
+
+        // The same file at the same time always gives the same levels
+        let mut again = Vec::new();
+        band_levels(&wav, Duration::from_millis(500), 8, &mut again);
+        assert_eq!(levels, again);
+    }
+
+    #[test]
+    fn silence_before_the_start() {
+        let wav = WavFile::parse(&wav(44100, &sine(44100, 1000.0, 0.8))).unwrap();
+        let mut levels = Vec::new();
+        band_levels(&wav, Duration::ZERO, 8, &mut levels);
+        assert_eq!(levels, [0.0; 8]);
+    }
+}
@@ -1,184 +1,525 @@
-use std::{os::raw::c_void, sync::mpsc::{self, Receiver, Sender}, time::{Duration, Instant}};
+use std::{path::Path, sync::{mpsc::{self, Receiver, Sender}, Arc}, time::{Duration, Instant}};
 
-use effects::{CorsairLedColorf32, Ledsf32};
-use icue_bindings::{types::{CorsairDeviceId, CorsairDeviceType, CorsairLedLuid, CorsairLedPosition, CorsairSessionState}, CorsairConnect, CorsairGetDevices, CorsairGetLedPositions, CorsairSetLedColors};
+use config_parse::{Action, Profile};
+#[cfg(not(windows))]
+use config_parse::Config;
+use sis_core::{BlendMode, DeviceTarget, DeviceType, Placement, Timing, VirtualKey};
+use icue_bindings::{types::{CorsairDeviceId, CorsairDeviceType, CorsairLedLuid, CorsairLedPosition, CorsairMacroKeyId, CorsairSessionState, KeyName}, CorsairConnect, CorsairDisconnect, CorsairGetDevices, CorsairGetLedLuidForKeyName, CorsairGetLedPositions};
 
-use self::effects::{floatled_to_colorled, ripple_effect, ripple_key, static_effect, static_key, wave_effect, wave_key, Effect, LedInfof32};
+use crate::{audio::open_source, layout::{key_leds, Layout}, metrics::{start_sampling, SystemMetrics}, scheduler::{Pace, Wake}};
 
-static mut STATE: CorsairSessionState = CorsairSessionState::Invalid;
+use self::{effects::{audio::AudioEffect, image::Image, Effect}, frame::{EffectStack, Layer, PressedKey}, lighting::{Lighting, LitDevice}, output::Output, session::{Session, SessionDevice}};
 
-pub(crate) mod effects;
+/// iCUE LED group of the G keys of a keyboard. LED ids are `group << 16 | index`, and the index of G1 is 1
+const G_KEYS_LED_GROUP: u32 = 1;
+/// Only the LEDs that change are sent to iCUE, so every LED is sent again this often in case another program changed them
+const FULL_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+pub mod compositor;
+pub mod effects;
+pub mod frame;
+pub(crate) mod lighting;
+pub(crate) mod output;
+pub(crate) mod session;
 
 pub(crate) enum CorsairMsg {
-    Connected,
-    NotConnected,
-    AddEffect(Box<Effect>),
-    AddEffectLed(CorsairLedLuid, Box<Effect>),
-    RemoveAllEffects
+    /// Only sent by the iCUE session
+    SessionState(CorsairSessionState),
+    /// The timing of the layer counts from when it is received
+    AddEffect(Box<Layer>),
+    /// The layer only applies to the LED of the key
+    AddKeyEffect(VirtualKey, Box<Layer>),
+    RemoveAllEffects,
+    /// The current effects fade out for this long, while the next ones (sent after this) fade in
+    FadeOut(Duration),
+    SetDesk(Vec<Placement>),
+    SetOutput(Box<Output>),
+    SetFps(u32),
+    /// Between 0.0 and 1.0
+    SetBrightness(f32),
+    /// Added to the brightness
+    ChangeBrightness(f32),
+    KeyPressed(VirtualKey),
+    /// Only sent by the iCUE session
+    MacroKeyPressed(CorsairDeviceId, CorsairMacroKeyId),
 }
 
-fn corsair_handler(
-    state: CorsairSessionState,
-    details: icue_bindings::types::CorsairSessionDetails,
-    tx: &Sender<CorsairMsg>
-) {
-    unsafe {
-        STATE = state
-    }
-    if state == CorsairSessionState::Connected {
-        tx.send(CorsairMsg::Connected).unwrap();
-    } else {
-        tx.send(CorsairMsg::NotConnected).unwrap();
+/// Starts the lighting thread, that connects to iCUE through `session`
+pub(crate) fn init_corsair(session: Box<dyn Session>) -> Sender<CorsairMsg> {
+    let (tx, rx) = mpsc::channel();
+    let listener_tx = tx.clone();
+    std::thread::spawn(move || listener(rx, CorsairState::new(listener_tx, session)));
+
+    tx
+}
+
+fn listener(rx: Receiver<CorsairMsg>, mut corsair_state: CorsairState) {
+    corsair_state.connect();
+    loop {
+        match corsair_state.lighting.scheduler.wait(&rx, corsair_state.pace()) {
+            Wake::Message(msg) => corsair_state.handle_msg(msg),
+            Wake::Frame => {
+                let start = Instant::now();
+                corsair_state.tick();
+                corsair_state.lighting.scheduler.frame_done(start)
+            },
+        }
     }
 }
 
-pub(crate) fn init_corsair() -> Sender<CorsairMsg> {
-    unsafe {
-        let context: *mut c_void = ((&mut [0;2]) as *mut i32) as *mut c_void; // Allocate sizeof::<i32>() * 2 = 4*2 = 8 bytes
-        let (tx, rx) = mpsc::channel();
-        let corsair_tx = tx.clone();
-        let _ = CorsairConnect(
-            Some(Box::new(move |state, details| {
-                corsair_handler(state, details, &tx)
-            })),
-            context
-        );
-        std::thread::spawn(||listener(rx));
+/// The light effects of a profile (and the devices they apply to), in the order they have to be applied.
+/// Their timings count from `start`, like the `t` of `Compositor::render`
+pub(crate) fn profile_effects(profile: &Profile, start: Duration) -> EffectStack {
+    let actions = profile.one_time_actions.iter()
+        .chain(profile.loop_actions.iter());
+
+    let mut effects = EffectStack::new();
+    for action in actions {
+        add_light_effect(&mut effects, start, DeviceTarget::All, None, BlendMode::Normal, Timing::default(), action)
+    }
+
+    effects
+}
 
-        corsair_tx
+/// Shows the lights of `profile` with the desk and the settings of `config`, for when there are no hotkeys to switch profiles
+#[cfg(not(windows))]
+pub(crate) fn show_profile(corsair_sender: &Sender<CorsairMsg>, config: &Config, profile: &Profile) {
+    corsair_sender.send(CorsairMsg::SetDesk(config.desk.clone())).unwrap();
+    corsair_sender.send(CorsairMsg::SetOutput(Box::new(Output::new(&config.settings)))).unwrap();
+    corsair_sender.send(CorsairMsg::SetFps(config.settings.fps)).unwrap();
+    let effects = profile_effects(profile, Duration::ZERO);
+    for layer in effects.effects {
+        corsair_sender.send(CorsairMsg::AddEffect(Box::new(layer))).unwrap();
+    }
+    for (key, layer) in effects.key_effects {
+        corsair_sender.send(CorsairMsg::AddKeyEffect(key, Box::new(layer))).unwrap();
     }
 }
 
-fn listener(rx: Receiver<CorsairMsg>) {
-    let mut connected = false;
-    let mut corsair_state = CorsairState::new();
-    loop {
-        if !connected {
-            while let Ok(msg) = rx.recv() {
-                corsair_state.handle_msg(&mut connected, msg);
-                if connected {
-                    break;
+/// Does nothing if the action isn't a light effect
+fn add_light_effect(effects: &mut EffectStack, start: Duration, target: DeviceTarget, zone: Option<Vec<VirtualKey>>, blend: BlendMode, timing: Timing, action: &Action) {
+    match action {
+        Action::OnDevice(target, action) => add_light_effect(effects, start, target.clone(), zone, blend, timing, action),
+        Action::Zone(zone, action) => add_light_effect(effects, start, target, Some(zone.clone()), blend, timing, action),
+        Action::Blend(blend, action) => add_light_effect(effects, start, target, zone, *blend, timing, action),
+        Action::Timed(timing, action) => add_light_effect(effects, start, target, zone, blend, *timing, action),
+        Action::KeyEffect(key, action) => {
+            // The effect of the key can have its own blend mode and timing
+            let (mut blend, mut timing, mut action) = (blend, timing, action.as_ref());
+            loop {
+                match action {
+                    Action::Blend(key_blend, key_action) => (blend, action) = (*key_blend, key_action),
+                    Action::Timed(key_timing, key_action) => (timing, action) = (*key_timing, key_action),
+                    _ => break,
                 }
             }
-            connected = true;
-            corsair_state.setup()
-        } else {
-            match rx.try_recv() {
-                Ok(msg) => corsair_state.handle_msg(&mut connected, msg),
-                Err(err) => match err {
-                    mpsc::TryRecvError::Empty => corsair_state.tick(),
-                    mpsc::TryRecvError::Disconnected => panic!("Channel closed"),
-                },
+            if let Some(effect) = to_effect(action) {
+                effects.add_key_effect(*key, Layer {
+                    target,
+                    zone: None,
+                    blend,
+                    timing,
+                    start,
+                    effect,
+                })
             }
+        },
+        action => if let Some(effect) = to_effect(action) {
+            effects.add_effect(Layer {
+                target,
+                zone,
+                blend,
+                timing,
+                start,
+                effect,
+            })
+        },
+    }
+}
+
+fn to_effect(action: &Action) -> Option<Effect> {
+    match action {
+        Action::StaticColor(color) => Some(Effect::Static(color.clone().into())),
+        Action::RippleEffect(ripple) => Some(Effect::Ripple(ripple.clone())),
+        Action::WaveEffect(wave) => Some(Effect::Wave(wave.clone())),
+        Action::FlashEffect(flash) => Some(Effect::Flash(flash.clone())),
+        Action::KeyRippleEffect(ripple) => Some(Effect::KeyRipple(ripple.clone())),
+        Action::HeatMapEffect(heat_map) => Some(Effect::HeatMap(heat_map.clone())),
+        Action::ColorChangeEffect(colorchange) => Some(Effect::ColorChange(colorchange.clone())),
+        Action::RainbowSpiralEffect(spiral) => Some(Effect::RainbowSpiral(spiral.clone())),
+        Action::StarfieldEffect(starfield) => Some(Effect::Starfield(starfield.clone())),
+        Action::FireEffect(fire) => Some(Effect::Fire(fire.clone())),
+        Action::PlasmaEffect(plasma) => Some(Effect::Plasma(plasma.clone())),
+        Action::LinearGradient(gradient) => Some(Effect::LinearGradient(gradient.clone())),
+        Action::RadialGradient(gradient) => Some(Effect::RadialGradient(gradient.clone())),
+        Action::ImageEffect(image) => match Image::load(Path::new(&image.path), image.fit) {
+            Ok(loaded) => Some(Effect::Image(Arc::new(loaded))),
+            Err(err) => {
+                eprintln!("Couldn't load the image {}: {err}", image.path);
+                None
+            },
+        },
+        Action::GaugeEffect(gauge) => {
+            start_sampling(SystemMetrics::default());
+            Some(Effect::Gauge(gauge.clone()))
+        },
+        Action::AudioEffect(audio) => match open_source(&audio.source) {
+            Ok(source) => Some(Effect::Audio(AudioEffect {
+                source,
+                animation: audio.animation.clone(),
+                bands: audio.bands,
+                mode: audio.mode,
+            })),
+            Err(err) => {
+                eprintln!("Couldn't open the audio source {}: {err}", audio.source);
+                None
+            },
+        },
+        _ => None,
+    }
+}
+
+fn device_type(type_: CorsairDeviceType) -> Option<DeviceType> {
+    Some(match type_ {
+        CorsairDeviceType::Keyboard => DeviceType::Keyboard,
+        CorsairDeviceType::Mouse => DeviceType::Mouse,
+        CorsairDeviceType::Mousemat => DeviceType::Mousemat,
+        CorsairDeviceType::Headset => DeviceType::Headset,
+        CorsairDeviceType::HeadsetStand => DeviceType::HeadsetStand,
+        CorsairDeviceType::FanLedController => DeviceType::FanLedController,
+        CorsairDeviceType::LedController => DeviceType::LedController,
+        CorsairDeviceType::MemoryModule => DeviceType::MemoryModule,
+        CorsairDeviceType::Cooler => DeviceType::Cooler,
+        CorsairDeviceType::Motherboard => DeviceType::Motherboard,
+        CorsairDeviceType::GraphicsCard => DeviceType::GraphicsCard,
+        CorsairDeviceType::Touchbar => DeviceType::Touchbar,
+        CorsairDeviceType::GameController => DeviceType::GameController,
+        CorsairDeviceType::Unknown | CorsairDeviceType::All => return None,
+    })
+}
+
+/// Connects to iCUE just to read the LEDs of every device
+pub(crate) fn device_layouts() -> Result<Vec<Layout>, String> {
+    let (tx, rx) = mpsc::channel();
+    unsafe {
+        CorsairConnect(
+            Some(Box::new(move |state, _details| {
+                let _ = tx.send(state);
+            })),
+            std::ptr::null_mut()
+        ).map_err(|err| format!("{err:?}"))?;
+    }
+    loop {
+        match rx.recv_timeout(Duration::from_secs(10)) {
+            Ok(CorsairSessionState::Connected) => break,
+            Ok(_) => (),
+            Err(_) => return Err("Timed out waiting for iCUE".into()),
         }
     }
+
+    let devices = unsafe {
+        CorsairGetDevices().map_err(|err| format!("{err:?}"))?
+    };
+    let mut layouts = Vec::new();
+    for device in devices {
+        let positions = CorsairGetLedPositions(&device.id).map_err(|err| format!("{err:?}"))?;
+        let is_keyboard = device.type_ == CorsairDeviceType::Keyboard;
+        let name = |id: CorsairLedLuid| match Layout::key_name(id) {
+            Some(name) if is_keyboard => name.to_string(),
+            _ => format!("Led{}", id.get()),
+        };
+        layouts.push(Layout::from_positions(device.model.to_string_lossy().into_owned(), device_type(device.type_), &positions, name))
+    }
+    let _ = CorsairDisconnect();
+
+    Ok(layouts)
+}
+
+/// Letters are asked to iCUE, so they are right on non QWERTY keyboards
+fn keyboard_keys(device_id: &CorsairDeviceId, leds: &[CorsairLedPosition]) -> Vec<(VirtualKey, CorsairLedLuid)> {
+    key_leds(|key, id, _| {
+        let led_id = match key as u16 {
+            letter @ 0x41..=0x5A => CorsairGetLedLuidForKeyName(device_id, KeyName::new(letter as u8 as char).unwrap()).ok()?,
+            _ => CorsairLedLuid::new(id),
+        };
+
+        leds.iter().any(|led| led.id == led_id).then_some(led_id)
+    })
 }
 
 struct CorsairState {
-    start_time: Instant,
-    keyboard_id: Option<CorsairDeviceId>,
-    leds: Vec<CorsairLedPosition>,
-    effects: Vec<Effect>,
-    key_effects: Vec<(CorsairLedLuid, Effect)>,
+    /// Where iCUE events are sent to
+    tx: Sender<CorsairMsg>,
+    session: Box<dyn Session>,
+    session_state: CorsairSessionState,
+    /// When to connect again, after iCUE couldn't be reached
+    retry_at: Option<Instant>,
+    retry_delay: Duration,
+    lighting: Lighting,
+    /// Only while connected
+    devices: Vec<LitDevice<CorsairDeviceId>>,
+    last_full_refresh: Instant,
 }
 
 impl CorsairState {
-    fn new() -> CorsairState {
+    fn new(tx: Sender<CorsairMsg>, session: Box<dyn Session>) -> CorsairState {
         CorsairState {
-            start_time: Instant::now(),
-            keyboard_id: None,
-            leds: Vec::new(),
-            effects: Vec::new(),
-            key_effects: Vec::new(),
+            tx,
+            session,
+            session_state: CorsairSessionState::Closed,
+            retry_at: None,
+            retry_delay: MIN_RETRY_DELAY,
+            lighting: Lighting::new(),
+            devices: Vec::new(),
+            last_full_refresh: Instant::now(),
         }
     }
 
-    fn setup(&mut self) {
-        self.start_time = Instant::now();
-        unsafe {
-            let devices = CorsairGetDevices().unwrap();
-            for device in devices {
-                println!("Device found:");
-                dbg!(&device);
-                if device.type_ == CorsairDeviceType::Keyboard {
-                    self.keyboard_id = Some(device.id)
-                }
-            }
+    fn connect(&mut self) {
+        if let Err(err) = self.session.connect(self.tx.clone()) {
+            eprintln!("Couldn't connect to iCUE: {err:?}");
+            self.retry_later()
+        }
+    }
 
-            // TODO: Do Option<String> instead of String for this reason
-            if let Some(id) = &self.keyboard_id {
-                self.leds = CorsairGetLedPositions(id).unwrap();
-            }
+    /// Connects again after the retry delay, which doubles every time
+    fn retry_later(&mut self) {
+        eprintln!("Retrying to connect to iCUE in {:?}", self.retry_delay);
+        self.retry_at = Some(Instant::now() + self.retry_delay);
+        self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+    }
+
+    fn pace(&self) -> Pace {
+        match self.retry_at {
+            Some(retry_at) => Pace::IdleFor(retry_at.saturating_duration_since(Instant::now())),
+            None if self.session_state != CorsairSessionState::Connected => Pace::Idle,
+            None if self.lighting.up_to_date => Pace::IdleFor(FULL_REFRESH_INTERVAL),
+            None => Pace::Animated,
         }
     }
 
+    fn set_session_state(&mut self, state: CorsairSessionState) {
+        self.session_state = state;
+        if state != CorsairSessionState::Connected {
+            // They may not be there anymore when the session comes back
+            self.devices = Vec::new();
+        }
+        match state {
+            CorsairSessionState::Connected => {
+                println!("Connected to iCUE");
+                self.retry_at = None;
+                self.retry_delay = MIN_RETRY_DELAY;
+                self.setup()
+            },
+            CorsairSessionState::Connecting => println!("Connecting to iCUE"),
+            // The SDK reconnects on its own
+            CorsairSessionState::ConnectionLost => eprintln!("Lost the connection to iCUE"),
+            CorsairSessionState::Timeout => {
+                eprintln!("Timed out connecting to iCUE");
+                self.retry_later()
+            },
+            CorsairSessionState::ConnectionRefused => {
+                eprintln!("iCUE refused the connection, is the SDK enabled in its settings?");
+                self.retry_later()
+            },
+            CorsairSessionState::Closed | CorsairSessionState::Invalid => eprintln!("The iCUE session is closed"),
+        }
+    }
+
+    /// Finds the devices of the session
+    fn setup(&mut self) {
+        let devices = match self.session.devices() {
+            Ok(devices) => devices,
+            Err(err) => {
+                eprintln!("Couldn't get the iCUE devices: {err:?}");
+                self.session.disconnect();
+                self.retry_later();
+                return
+            },
+        };
+        self.session.subscribe_for_events(&devices, self.tx.clone());
+        self.devices = self.lighting.devices(devices.into_iter()
+            .map(|SessionDevice { id, info, leds }| (id, info, leds))
+            .collect());
+    }
+
     fn tick(&mut self) {
-        if let Some(keyboard_id) = &self.keyboard_id {
-            // TODO: Improve performance. Too many clones
-            let dt = self.start_time.elapsed().as_millis() as u64;
-            //let nanos = self.start_time.elapsed().as_nanos() as u64;
-            let mut leds: Ledsf32<'_> = Box::new(self.leds.iter()
-                .map(|led| {
-                    ((led.cx, led.cy), CorsairLedColorf32 {
-                        id: led.id,
-                        color: (0.0, 0.0, 0.0, 1.0)
-                    })
-            }));
-            for effect in self.effects.iter() {
-                match effect {
-                    Effect::Static(color) => leds = static_effect(leds, color.clone()),
-                    Effect::Wave(wave) => leds = wave_effect(leds, dt, &wave),
-                    Effect::Ripple(ripple) => leds = ripple_effect(leds, dt, &ripple),
-                    Effect::ColorChange => (),
-                }
-            }
+        if self.retry_at.is_some_and(|retry_at| retry_at <= Instant::now()) {
+            self.retry_at = None;
+            self.session.disconnect();
+            self.connect();
+            return
+        }
+        if self.session_state != CorsairSessionState::Connected {
+            return
+        }
 
-            for (key, effect) in self.key_effects.iter() {
-                let effect: Box<dyn Fn(LedInfof32) -> LedInfof32> = match effect {
-                    Effect::Static(color) => Box::new(move |key| static_key(key, color.clone())),
-                    Effect::Wave(wave) => Box::new(move |key| wave_key(key, dt, &wave)),
-                    Effect::Ripple(ripple) => Box::new(move |key| ripple_key(key, dt, &ripple)),
-                    Effect::ColorChange => Box::new(move |key| key),
-                };
-
-                leds = Box::new(leds.map(move |led| {
-                    if led.1.id == *key {
-                        effect(led)
-                    } else {
-                        led
-                    }
-                }))
+        if self.last_full_refresh.elapsed() >= FULL_REFRESH_INTERVAL {
+            self.last_full_refresh = Instant::now();
+            for device in self.devices.iter_mut() {
+                device.sent.clear()
             }
+        }
 
-            let leds: Vec<_> = floatled_to_colorled(leds).map(|(_, led)| led).collect();
-            unsafe {
-                CorsairSetLedColors(keyboard_id, leds).unwrap();
+        let updates: Vec<_> = self.lighting.render(&mut self.devices).into_iter()
+            .enumerate()
+            .filter(|(_, changed)| !changed.is_empty())
+            .collect();
+
+        // The LEDs of several devices are sent together, so they change at the same time
+        let buffered = updates.len() > 1;
+        for (index, changed) in updates {
+            let device = &mut self.devices[index];
+            let res = if buffered {
+                self.session.set_led_colors_buffer(&device.backend, changed)
+            } else {
+                self.session.set_led_colors(&device.backend, changed)
+            };
+            if let Err(err) = res {
+                eprintln!("Couldn't set the colors of {}: {err:?}", device.info.model);
+                device.sent.clear()
+            }
+        }
+        if buffered {
+            if let Err(err) = self.session.flush_led_colors() {
+                eprintln!("Couldn't send the colors to iCUE: {err:?}");
+                for device in self.devices.iter_mut() {
+                    device.sent.clear()
+                }
             }
-            // TODO: Allow change the thread::sleep time from config file
-            std::thread::sleep(Duration::from_millis(100)) // Refresh color once per second (+ the time it takes to update)
         }
     }
 
-    fn add_effect(&mut self, effect: Effect) {
-        self.effects.push(effect)
+    fn handle_msg(&mut self, msg: CorsairMsg) {
+        match msg {
+            CorsairMsg::SessionState(state) => self.set_session_state(state),
+            CorsairMsg::MacroKeyPressed(device_id, key_id) => {
+                if let Some(device) = self.devices.iter().find(|device| device.backend == device_id) {
+                    let key = PressedKey::Led {
+                        serial: device.info.serial.clone(),
+                        led: CorsairLedLuid::new(G_KEYS_LED_GROUP << 16 | key_id as u32),
+                    };
+                    self.lighting.press_key(key)
+                }
+            },
+            msg => self.lighting.handle_msg(msg, &mut self.devices),
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use sis_core::RGBA;
+
+    use super::{session::fake::{FakeIcue, FakeSession}, *};
 
-    fn add_effect_led(&mut self, led: CorsairLedLuid, effect: Effect) {
-        self.key_effects.push((led, effect))
+    /// The lighting thread, driven by hand instead of by `listener`
+    struct Lights {
+        corsair: CorsairState,
+        icue: Arc<Mutex<FakeIcue>>,
+        _rx: Receiver<CorsairMsg>,
     }
 
-    fn handle_msg(&mut self, connected: &mut bool, msg: CorsairMsg) {
-        match msg {
-            CorsairMsg::Connected => *connected = true,
-            CorsairMsg::NotConnected => *connected = false,
-            CorsairMsg::AddEffect(effect) => self.add_effect(*effect),
-            CorsairMsg::AddEffectLed(led, effect) => self.add_effect_led(led, *effect),
-            CorsairMsg::RemoveAllEffects => self.remove_all_effects(),
+    impl Lights {
+        fn new() -> Lights {
+            let session = FakeSession::new(&[Layout::sixty_percent()]);
+            let icue = session.icue.clone();
+            let (tx, rx) = mpsc::channel();
+            let mut corsair = CorsairState::new(tx, Box::new(session));
+            corsair.handle_msg(CorsairMsg::AddEffect(Box::new(Layer::everywhere(Effect::Static((1.0, 0.0, 0.0, 1.0))))));
+            corsair.connect();
+
+            Lights { corsair, icue, _rx: rx }
+        }
+
+        /// Like iCUE, the state changes and then the lighting thread is told
+        fn set_state(&mut self, state: CorsairSessionState) {
+            self.icue.lock().unwrap().state = state;
+            self.corsair.handle_msg(CorsairMsg::SessionState(state))
+        }
+
+        /// Sets the state, and returns how long the lighting thread waits before connecting again
+        fn fail(&mut self, state: CorsairSessionState) -> Duration {
+            let before = Instant::now();
+            self.set_state(state);
+            self.corsair.retry_at.expect("No retry") - before
+        }
+
+        /// Runs the retry that is due, as if its time had come
+        fn retry(&mut self) {
+            self.corsair.retry_at = Some(Instant::now());
+            self.corsair.tick();
+        }
+
+        /// The colors sent by the next frame
+        fn frame(&mut self) -> Vec<RGBA> {
+            self.corsair.tick();
+            std::mem::take(&mut self.icue.lock().unwrap().colors).into_iter()
+                .map(|(_, color)| (color.r, color.g, color.b, color.a))
+                .collect()
         }
     }
 
-    fn remove_all_effects(&mut self) {
-        self.effects = Vec::new();
-        self.key_effects = Vec::new();
+    #[track_caller]
+    fn assert_about(delay: Duration, expected: Duration) {
+        assert!(delay >= expected && delay < expected + Duration::from_millis(500), "{delay:?} instead of {expected:?}");
+    }
+
+    #[test]
+    fn reconnects_until_connected() {
+        let mut lights = Lights::new();
+        assert_eq!(lights.icue.lock().unwrap().connections, 1);
+        lights.set_state(CorsairSessionState::Connecting);
+        assert_eq!(lights.corsair.pace(), Pace::Idle);
+        assert!(lights.frame().is_empty());
+
+        let mut delays = Vec::new();
+        delays.push(lights.fail(CorsairSessionState::Timeout));
+        for _ in 0..6 {
+            lights.retry();
+            delays.push(lights.fail(CorsairSessionState::ConnectionRefused));
+        }
+        assert_eq!(lights.icue.lock().unwrap().connections, 7);
+        for (delay, expected) in delays.into_iter().zip([1, 2, 4, 8, 16, 30, 30]) {
+            assert_about(delay, Duration::from_secs(expected))
+        }
+        assert!(matches!(lights.corsair.pace(), Pace::IdleFor(delay) if delay <= Duration::from_secs(30)));
+
+        lights.retry();
+        lights.set_state(CorsairSessionState::Connected);
+        assert_eq!(lights.corsair.retry_at, None);
+        assert_eq!(lights.icue.lock().unwrap().enumerations, 1);
+        let frame = lights.frame();
+        assert!(!frame.is_empty() && frame.iter().all(|color| *color == (255, 0, 0, 255)), "{frame:?}");
+
+        // Connected again, the backoff starts over
+        assert_about(lights.fail(CorsairSessionState::Timeout), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn lost_connections_find_the_devices_again() {
+        let mut lights = Lights::new();
+        lights.set_state(CorsairSessionState::Connected);
+        let leds = lights.frame().len();
+        assert_eq!(leds, Layout::sixty_percent().leds.len());
+
+        lights.set_state(CorsairSessionState::ConnectionLost);
+        assert!(lights.corsair.devices.is_empty());
+        // The SDK reconnects on its own
+        assert_eq!(lights.corsair.retry_at, None);
+        assert!(lights.frame().is_empty());
+
+        lights.set_state(CorsairSessionState::Connected);
+        assert_eq!(lights.icue.lock().unwrap().enumerations, 2);
+        assert_eq!(lights.icue.lock().unwrap().connections, 1);
+        // The effects survived, and every LED is sent to the devices found again
+        assert_eq!(lights.corsair.lighting.effects.effects.len(), 1);
+        let frame = lights.frame();
+        assert_eq!(frame.len(), leds);
+        assert!(frame.iter().all(|color| *color == (255, 0, 0, 255)), "{frame:?}");
     }
 }
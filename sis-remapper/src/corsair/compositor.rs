@@ -0,0 +1,328 @@
+//! Renders the effects of a device into a frame. Everything a frame needs is kept from one frame to the next,
+//! so rendering doesn't allocate once the buffers have their size
+
+use std::{f64::consts::TAU, time::Duration};
+
+use cgmath::Angle;
+use icue_bindings::types::{CorsairLedPosition, CorsairLedLuid};
+use sis_core::{BlendMode, DevicePoint, RGBAf32, VirtualKey};
+
+use crate::{audio::band_levels, metrics::latest_metrics};
+
+use super::{effects::{audio::audio_key, blend::blend, colorchange_color, corner_range, flash_key, gauge_key, heat_map_key, image::image_key, key_ripple_key, linear_gradient_key, procedural::{fire_key, plasma_key, rainbow_spiral_key, starfield_key}, radial_gradient_key, rgbaf32_to_rgbau8, ripple_key, static_key, wave_key, Bounds, Effect, Press}, frame::{DeviceInfo, EffectStack, Frame, KeyPress, Layer, PressedKey}};
+
+/// Per LED values that only depend on where the LEDs are
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Geometry {
+    /// The distance to a point
+    Distance((f64, f64)),
+    /// The position along a direction, as (cos, sin)
+    Along((f64, f64)),
+    /// The angle around a point, in turns
+    Angle((f64, f64)),
+}
+
+/// The LEDs of the frame an effect is applied to
+#[derive(Debug, Clone, Copy)]
+enum Area<'a> {
+    All,
+    /// The LEDs that are `true`
+    Zone(&'a [bool]),
+    Led(usize),
+}
+
+/// Where the LEDs of a device are
+#[derive(Debug)]
+struct DeviceLeds {
+    /// On the desk
+    positions: Vec<CorsairLedPosition>,
+    /// The index of the LED of each key, for key effects and zones
+    keys: Vec<(VirtualKey, usize)>,
+    bounds: Bounds,
+    /// Computed the first time an effect needs them, the same effects need them again every frame
+    geometry: Vec<(Geometry, Vec<f64>)>,
+}
+
+/// Renders the frames of a device. The LEDs of the device must not move, a new compositor is needed when they do
+#[derive(Debug)]
+pub struct Compositor {
+    leds: DeviceLeds,
+    /// The color of every LED, in the order of the positions
+    colors: Vec<RGBAf32>,
+    /// The colors of the effects that fade out
+    previous_colors: Vec<RGBAf32>,
+    /// The LEDs of the zone being rendered
+    zone: Vec<bool>,
+    presses: Vec<Press>,
+    /// The levels of the bands of the audio effect being rendered
+    levels: Vec<f32>,
+    frame: Frame,
+}
+
+impl Compositor {
+    /// `positions` are the positions of the LEDs of `device`, effects are measured on the device alone
+    pub fn new(device: &DeviceInfo, positions: Vec<CorsairLedPosition>) -> Compositor {
+        let bounds = Bounds::new(&positions);
+        Compositor::with_bounds(device, positions, bounds)
+    }
+
+    /// `positions` are the positions of the LEDs of `device` on the desk. Effects find their centers, edges
+    /// and speeds in `bounds`, which can be the bounds of every LED on the desk
+    pub(crate) fn with_bounds(device: &DeviceInfo, positions: Vec<CorsairLedPosition>, bounds: Bounds) -> Compositor {
+        let keys = device.keys.iter()
+            .filter_map(|(key, led)| Some((key.clone(), positions.iter().position(|position| position.id == *led)?)))
+            .collect();
+
+        Compositor {
+            colors: vec![(0.0, 0.0, 0.0, 1.0); positions.len()],
+            previous_colors: vec![(0.0, 0.0, 0.0, 1.0); positions.len()],
+            zone: vec![false; positions.len()],
+            presses: Vec::new(),
+            levels: Vec::new(),
+            frame: positions.iter().map(|led| (led.id, (0, 0, 0, 255))).collect(),
+            leds: DeviceLeds {
+                positions,
+                keys,
+                bounds,
+                geometry: Vec::new(),
+            },
+        }
+    }
+
+    /// Applies every effect targeting `device` (and then every key effect) on top of a black background.
+    /// During a fade, the frame of the previous effects is mixed with the frame of the current ones.
+    ///
+    /// `t` is the time since the effects started. There is no I/O and no clock involved,
+    /// so the same device, positions, effects and `t` always give the same frame.
+    pub fn render(&mut self, device: &DeviceInfo, effects: &EffectStack, t: Duration) -> &mut Frame {
+        self.update_presses(device, &effects.presses, t);
+        let mut colors = std::mem::take(&mut self.colors);
+        self.render_layers(&mut colors, device, &effects.effects, &effects.key_effects, t);
+
+        if let Some(fade) = effects.fading_out.as_ref().filter(|fade| !fade.is_finished(t)) {
+            let mut previous_colors = std::mem::take(&mut self.previous_colors);
+            self.render_layers(&mut previous_colors, device, &fade.effects, &fade.key_effects, t);
+            let progress = t.saturating_sub(fade.start).as_secs_f32() / fade.duration.as_secs_f32();
+            // Mixed before rounding, so slow fades don't step
+            let mix = |previous: f32, current: f32| previous + (current - previous) * progress;
+            for (color, previous) in colors.iter_mut().zip(previous_colors.iter()) {
+                *color = (mix(previous.0, color.0), mix(previous.1, color.1), mix(previous.2, color.2), mix(previous.3, color.3));
+            }
+            self.previous_colors = previous_colors;
+        }
+
+        for ((_, led_color), color) in self.frame.iter_mut().zip(colors.iter()) {
+            *led_color = rgbaf32_to_rgbau8(*color)
+        }
+        self.colors = colors;
+
+        &mut self.frame
+    }
+
+    fn render_layers(&mut self, colors: &mut [RGBAf32], device: &DeviceInfo, layers: &[Layer], key_effects: &[(VirtualKey, Layer)], t: Duration) {
+        let t_millis = t.as_millis() as u64;
+        colors.fill((0.0, 0.0, 0.0, 1.0));
+        for layer in layers.iter().filter(|layer| device.is_target(&layer.target)) {
+            let dt = match layer.effect_time(t) {
+                Some(effect_time) => effect_time.as_millis() as u64,
+                None => continue,
+            };
+            match &layer.zone {
+                None => apply_effect(colors, Area::All, layer.blend, &layer.effect, dt, t_millis, &mut self.leds, &self.presses, &mut self.levels),
+                Some(zone) => {
+                    self.zone.fill(false);
+                    for index in zone.iter().filter_map(|key| self.leds.key_index(key)) {
+                        self.zone[index] = true
+                    }
+                    apply_effect(colors, Area::Zone(&self.zone), layer.blend, &layer.effect, dt, t_millis, &mut self.leds, &self.presses, &mut self.levels)
+                },
+            }
+        }
+
+        for (key, layer) in key_effects.iter().filter(|(_, layer)| device.is_target(&layer.target)) {
+            let index = match self.leds.key_index(key) {
+                Some(index) => index,
+                None => continue, // The device doesn't have this key
+            };
+            let dt = match layer.effect_time(t) {
+                Some(effect_time) => effect_time.as_millis() as u64,
+                None => continue,
+            };
+            apply_effect(colors, Area::Led(index), layer.blend, &layer.effect, dt, t_millis, &mut self.leds, &self.presses, &mut self.levels)
+        }
+    }
+
+    /// The presses (up to `t`) of the keys of `device`, with the position of their LED
+    fn update_presses(&mut self, device: &DeviceInfo, presses: &[KeyPress], t: Duration) {
+        self.presses.clear();
+        let leds = &self.leds;
+        self.presses.extend(presses.iter()
+            .filter(|press| press.time <= t)
+            .filter_map(|press| {
+                let index = match &press.key {
+                    PressedKey::Key(key) => leds.key_index(key)?,
+                    PressedKey::Led { serial, led } if *serial == device.serial => leds.led_index(*led)?,
+                    PressedKey::Led { .. } => return None,
+                };
+                let position = &leds.positions[index];
+                Some(Press {
+                    led: position.id,
+                    pos: (position.cx, position.cy),
+                    time_millis: press.time.as_millis() as u64,
+                })
+            }))
+    }
+}
+
+impl DeviceLeds {
+    fn key_index(&self, key: &VirtualKey) -> Option<usize> {
+        self.keys.iter()
+            .find(|(device_key, _)| device_key == key)
+            .map(|(_, index)| *index)
+    }
+
+    fn led_index(&self, led: CorsairLedLuid) -> Option<usize> {
+        self.positions.iter().position(|position| position.id == led)
+    }
+
+    /// Where `point` is on the device, `bounds` are the bounds of the LEDs the effect is applied to
+    fn point(&self, point: &DevicePoint, bounds: &Bounds) -> (f64, f64) {
+        match point {
+            DevicePoint::Relative(x, y) => (
+                bounds.left + (bounds.right - bounds.left) * x,
+                bounds.top + (bounds.bottom - bounds.top) * y,
+            ),
+            DevicePoint::Key(key) => self.key_index(key)
+                .map(|index| (self.positions[index].cx, self.positions[index].cy))
+                .unwrap_or_else(|| bounds.center()),
+        }
+    }
+
+    /// Computes the values of `geometry` for every LED, if they weren't already
+    fn prepare(&mut self, geometry: Geometry) {
+        if self.geometry.iter().any(|(computed, _)| *computed == geometry) {
+            return
+        }
+
+        let values = self.positions.iter()
+            .map(|led| match geometry {
+                Geometry::Distance((x, y)) => f64::hypot(led.cx - x, led.cy - y),
+                Geometry::Along((cos, sin)) => led.cx * cos + led.cy * sin,
+                Geometry::Angle((x, y)) => f64::atan2(led.cy - y, led.cx - x) / TAU,
+            }).collect();
+        self.geometry.push((geometry, values))
+    }
+
+    /// The values of `geometry` for every LED, it must have been prepared
+    fn values(&self, geometry: Geometry) -> &[f64] {
+        self.geometry.iter()
+            .find(|(computed, _)| *computed == geometry)
+            .map(|(_, values)| values.as_slice())
+            .unwrap()
+    }
+}
+
+/// Applies `effect` to the LEDs of `area`. `dt` is how far into its animation the effect is,
+/// the effects that react to key presses use the time of the frame (`t_millis`) like the presses
+fn apply_effect(colors: &mut [RGBAf32], area: Area, mode: BlendMode, effect: &Effect, dt: u64, t_millis: u64, leds: &mut DeviceLeds, presses: &[Press], levels: &mut Vec<f32>) {
+    // Zones and key effects only mask the effect, it is measured on the whole device (or desk)
+    let bounds = &leds.bounds.clone();
+    match effect {
+        Effect::Static(effect_color) => paint(colors, area, mode, |_, color| static_key(color, *effect_color)),
+        Effect::Wave(wave) => {
+            let (sin, cos) = (wave.rotation.sin() as f64, wave.rotation.cos() as f64);
+            let direction = (cos, -sin);
+            let origin = if wave.two_sides {
+                let center = leds.point(&wave.center, bounds);
+                center.0 * direction.0 + center.1 * direction.1
+            } else {
+                // One sided waves start from the edge of the device they move away from
+                corner_range(bounds, direction).0
+            };
+            leds.prepare(Geometry::Along(direction));
+            let positions = leds.values(Geometry::Along(direction));
+            paint(colors, area, mode, |index, color| wave_key(color, positions[index] - origin, dt, wave, bounds))
+        },
+        Effect::Ripple(ripple) => {
+            let center = leds.point(&ripple.center, bounds);
+            leds.prepare(Geometry::Distance(center));
+            let distances = leds.values(Geometry::Distance(center));
+            paint(colors, area, mode, |index, color| ripple_key(color, distances[index], dt, ripple, bounds))
+        },
+        Effect::ColorChange(colorchange) => {
+            let effect_color = colorchange_color(dt, colorchange);
+            paint(colors, area, mode, |_, color| static_key(color, effect_color))
+        },
+        Effect::Flash(flash) => paint(colors, area, mode, |index, color| flash_key(color, leds.positions[index].id, t_millis, flash, presses)),
+        Effect::KeyRipple(ripple) => paint(colors, area, mode, |index, color| key_ripple_key(color, position(leds, index), t_millis, ripple, presses, bounds)),
+        Effect::HeatMap(heat_map) => paint(colors, area, mode, |index, color| heat_map_key(color, leds.positions[index].id, t_millis, heat_map, presses)),
+        Effect::RainbowSpiral(spiral) => {
+            let center = leds.point(&spiral.center, bounds);
+            leds.prepare(Geometry::Angle(center));
+            leds.prepare(Geometry::Distance(center));
+            let (angles, distances) = (leds.values(Geometry::Angle(center)), leds.values(Geometry::Distance(center)));
+            paint(colors, area, mode, |index, color| rainbow_spiral_key(color, angles[index], distances[index], dt, spiral, bounds))
+        },
+        Effect::Starfield(starfield) => paint(colors, area, mode, |index, color| starfield_key(color, leds.positions[index].id, dt, starfield)),
+        Effect::Fire(fire) => paint(colors, area, mode, |index, color| fire_key(color, position(leds, index), dt, fire, bounds)),
+        Effect::Plasma(plasma) => paint(colors, area, mode, |index, color| plasma_key(color, position(leds, index), dt, plasma, bounds)),
+        Effect::LinearGradient(gradient) => {
+            let direction = (gradient.angle.cos() as f64, gradient.angle.sin() as f64);
+            // The corners of the device are the start and the end of the gradient
+            let range = corner_range(bounds, direction);
+            leds.prepare(Geometry::Along(direction));
+            let positions = leds.values(Geometry::Along(direction));
+            paint(colors, area, mode, |index, color| linear_gradient_key(color, positions[index], gradient, range))
+        },
+        Effect::RadialGradient(gradient) => {
+            let center = leds.point(&gradient.center, bounds);
+            leds.prepare(Geometry::Distance(center));
+            let distances = leds.values(Geometry::Distance(center));
+            paint(colors, area, mode, |index, color| radial_gradient_key(color, distances[index], gradient, bounds))
+        },
+        Effect::Image(image) => paint(colors, area, mode, |index, color| image_key(color, position(leds, index), dt, image, bounds)),
+        Effect::Audio(audio) => {
+            // Analysed once per frame, not once per LED
+            band_levels(audio.source.as_ref(), Duration::from_millis(dt), audio.bands, levels);
+            paint(colors, area, mode, |index, color| audio_key(color, position(leds, index), audio, levels, bounds))
+        },
+        Effect::Gauge(gauge) => {
+            let load = latest_metrics().load(gauge.metric);
+            paint(colors, area, mode, |index, color| gauge_key(color, position(leds, index), gauge, load, bounds))
+        },
+    }
+}
+
+fn position(leds: &DeviceLeds, index: usize) -> (f64, f64) {
+    (leds.positions[index].cx, leds.positions[index].cy)
+}
+
+/// Replaces the color of each LED of `area` by `effect` applied to it. `effect` gets the index of the LED
+/// and the color below the effect, and combines its color with it with `mode`
+fn paint(colors: &mut [RGBAf32], area: Area, mode: BlendMode, effect: impl Fn(usize, RGBAf32) -> RGBAf32) {
+    let indices = match area {
+        Area::Led(index) => index..index + 1,
+        _ => 0..colors.len(),
+    };
+    for index in indices {
+        if let Area::Zone(zone) = area {
+            if !zone[index] {
+                continue
+            }
+        }
+
+        let color = colors[index];
+        colors[index] = if mode == BlendMode::Normal {
+            effect(index, color) // Every effect already alpha composes its color
+        } else {
+            // The color of the effect alone, on top of nothing
+            let over = effect(index, (0.0, 0.0, 0.0, 0.0));
+            let over = if over.3 > 0.0 {
+                over
+            } else {
+                (0.0, 0.0, 0.0, 0.0) // Alpha compositing two transparent colors isn't a number
+            };
+            blend(color, over, mode)
+        };
+    }
+}
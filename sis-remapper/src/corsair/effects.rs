@@ -1,132 +1,264 @@
-use cgmath::Angle;
-use icue_bindings::types::{CorsairLedColor, CorsairLedLuid};
-use sis_core::{rgbau8_to_rgbaf32, ColorAnimation, ColorChangeAnimation, RGBAf32, RippleAnimation, WaveAnimation, RGBA};
+use std::{sync::Arc, time::Duration};
 
-const LED_DISTANCE: f64 = 20.0;
+use icue_bindings::types::{CorsairLedLuid, CorsairLedPosition};
+use sis_core::{rgbau8_to_rgbaf32, ColorAnimation, ColorChangeAnimation, ColorChangeMode, FireAnimation, FlashAnimation, GaugeAnimation, GaugeMode, HeatMapAnimation, KeyRippleAnimation, LinearGradient, PlasmaAnimation, RadialGradient, RainbowSpiralAnimation, RGBAf32, RippleAnimation, StarfieldAnimation, WaveAnimation, RGBA};
 
-pub(crate) struct CorsairLedColorf32 {
-    pub id: CorsairLedLuid,
-    pub(crate) color: RGBAf32
-}
+pub mod audio;
+pub(crate) mod blend;
+pub mod image;
+pub(crate) mod procedural;
+
+use self::{audio::AudioEffect, image::Image};
 
-type LedInfo = ((f64, f64), CorsairLedColor);
-pub(crate) type LedInfof32 = ((f64, f64), CorsairLedColorf32);
-type Leds<'a> = Box<dyn Iterator<Item=LedInfo> + 'a>;
-pub(crate) type Ledsf32<'a> = Box<dyn Iterator<Item=LedInfof32> + 'a>;
+/// The distance between two keys of a standard keyboard (in mm), for devices with a single LED
+const DEFAULT_KEY_DISTANCE: f64 = 19.05;
 
 #[derive(Debug, Clone)]
-pub(crate) enum Effect {
+pub enum Effect {
     Static(RGBAf32),
     Wave(WaveAnimation),
     Ripple(RippleAnimation),
-    ColorChange,
+    ColorChange(ColorChangeAnimation),
+    Flash(FlashAnimation),
+    KeyRipple(KeyRippleAnimation),
+    HeatMap(HeatMapAnimation),
+    RainbowSpiral(RainbowSpiralAnimation),
+    Starfield(StarfieldAnimation),
+    Fire(FireAnimation),
+    Plasma(PlasmaAnimation),
+    LinearGradient(LinearGradient),
+    RadialGradient(RadialGradient),
+    /// Shared, so changing the effects doesn't copy every frame of the image
+    Image(Arc<Image>),
+    Audio(AudioEffect),
+    Gauge(GaugeAnimation),
+}
+
+impl Effect {
+    /// How long the effect takes to play once, `None` if it doesn't have an end
+    pub(crate) fn duration(&self) -> Option<Duration> {
+        match self {
+            Effect::Wave(wave) => Some(wave.duration),
+            Effect::Ripple(ripple) => Some(ripple.duration),
+            Effect::ColorChange(colorchange) => Some(colorchange.duration),
+            Effect::Image(image) if image.duration_millis() > 0 => Some(Duration::from_millis(image.duration_millis())),
+            _ => None,
+        }
+    }
+
+    /// Effects that always give the same colors. Effects that react to key presses aren't
+    pub(crate) fn is_static(&self) -> bool {
+        match self {
+            Effect::Static(_) | Effect::LinearGradient(_) | Effect::RadialGradient(_) => true,
+            Effect::Image(image) => image.duration_millis() == 0,
+            _ => false,
+        }
+    }
+}
+
+/// The rectangle around the centers of the LEDs of a device
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Bounds {
+    pub(crate) left: f64,
+    pub(crate) top: f64,
+    pub(crate) right: f64,
+    pub(crate) bottom: f64,
+    /// The usual distance between two neighbouring LEDs, effects measure their sizes and speeds in keys with it
+    pub(crate) key_distance: f64,
+}
+
+impl Bounds {
+    /// Compares every pair of LEDs, so it is computed once per layout instead of once per frame
+    pub(crate) fn new(leds: &[CorsairLedPosition]) -> Bounds {
+        Bounds::around(leds, key_distance(leds))
+    }
+
+    fn around<'a>(leds: impl IntoIterator<Item=&'a CorsairLedPosition>, key_distance: f64) -> Bounds {
+        let (left, top, right, bottom) = leds.into_iter()
+            .fold((f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY), |(left, top, right, bottom), led| {
+                (left.min(led.cx), top.min(led.cy), right.max(led.cx), bottom.max(led.cy))
+            });
+        if left > right {
+            // No LEDs
+            return Bounds { left: 0.0, top: 0.0, right: 0.0, bottom: 0.0, key_distance }
+        }
+
+        Bounds { left, top, right, bottom, key_distance }
+    }
+
+    pub(crate) fn center(&self) -> (f64, f64) {
+        ((self.left + self.right) / 2.0, (self.top + self.bottom) / 2.0)
+    }
+}
+
+/// The median distance from each LED to its nearest neighbour. Wide keys and gaps between
+/// groups of keys don't change it, unlike an average
+fn key_distance(leds: &[CorsairLedPosition]) -> f64 {
+    let mut distances = leds.iter()
+        .filter_map(|led| {
+            leds.iter()
+                .map(|other| f64::hypot(other.cx - led.cx, other.cy - led.cy))
+                // LEDs at the same position (like the LEDs of a single key) aren't neighbours
+                .filter(|distance| *distance > 0.0)
+                .reduce(f64::min)
+        }).collect::<Vec<_>>();
+    if distances.is_empty() {
+        return DEFAULT_KEY_DISTANCE
+    }
+    distances.sort_by(f64::total_cmp);
+
+    distances[distances.len() / 2]
 }
 
-pub(crate) fn floatled_to_colorled(leds: Ledsf32) -> Leds {
-    Box::new(leds.map(|(pos, CorsairLedColorf32 { id, color })| {
-        let (r,g,b,a) = rgbaf32_to_rgbau8(color);
-        (pos, CorsairLedColor {
-            id,
-            r,
-            g,
-            b,
-            a
-        })
-    }))
+/// The lowest and the highest position along `direction` (as (cos, sin)) of the corners of `bounds`
+pub(crate) fn corner_range(bounds: &Bounds, (cos, sin): (f64, f64)) -> (f64, f64) {
+    [
+        (bounds.left, bounds.top),
+        (bounds.right, bounds.top),
+        (bounds.left, bounds.bottom),
+        (bounds.right, bounds.bottom),
+    ].into_iter()
+        .map(|(x, y)| x * cos + y * sin)
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), position| (low.min(position), high.max(position)))
 }
 
-pub(crate) fn static_effect(leds: Ledsf32, effect_color: RGBAf32) -> Ledsf32 {
-    Box::new(leds.map(move |key| {
-        static_key(key, effect_color)
-    }))
+/// A key press that a device can show
+#[derive(Debug)]
+pub(crate) struct Press {
+    /// The LED of the key that was pressed
+    pub(crate) led: CorsairLedLuid,
+    pub(crate) pos: (f64, f64),
+    /// When the key was pressed, never after the frame being rendered
+    pub(crate) time_millis: u64,
 }
 
-pub(crate) fn static_key((pos, CorsairLedColorf32 {id, color}): LedInfof32, effect_color: RGBAf32) -> LedInfof32 {
-    (pos, CorsairLedColorf32 {
-        id,
-        color: alpha_compose(color, effect_color)
-    })
+pub(crate) fn static_key(color: RGBAf32, effect_color: RGBAf32) -> RGBAf32 {
+    alpha_compose(color, effect_color)
 }
 
-pub(crate) fn wave_effect<'a>(leds: Ledsf32<'a>, dt_millis: u64, wave: &'a WaveAnimation) -> Ledsf32<'a> {
-    Box::new(leds.map(move |key| {
-        wave_key(key, dt_millis, wave)
-    }))
+/// `position` is where the LED is along the gradient, `range` where the gradient starts and ends
+pub(crate) fn linear_gradient_key(color: RGBAf32, position: f64, gradient: &LinearGradient, (start, end): (f64, f64)) -> RGBAf32 {
+    let sample_point = if end > start {
+        ((position - start) / (end - start)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    alpha_compose(color, sample_animation(sample_point as f32, &gradient.animation))
+}
+
+/// `distance` is how far the LED is from the center of the gradient
+pub(crate) fn radial_gradient_key(color: RGBAf32, distance: f64, gradient: &RadialGradient, bounds: &Bounds) -> RGBAf32 {
+    let sample_point = (distance / bounds.key_distance / gradient.radius).clamp(0.0, 1.0);
+    alpha_compose(color, sample_animation(sample_point as f32, &gradient.animation))
 }
 
-pub(crate) fn wave_key((pos, CorsairLedColorf32 {id, color}): LedInfof32, dt_millis: u64, wave: &WaveAnimation) -> LedInfof32 {
-    const MIDPOINT: f64 = 100.0;
-    let wave_head = (dt_millis % wave.duration.as_millis() as u64) as f64 * wave.speed / 1000.0 * LED_DISTANCE;
-    let wave_width = wave.light_amount * LED_DISTANCE;
-    let pos_rotated = pos.0 * wave.rotation.cos() as f64- pos.1 * wave.rotation.sin() as f64;
+/// `position` is where the LED is along the wave, from where the wave starts
+pub(crate) fn wave_key(color: RGBAf32, position: f64, dt_millis: u64, wave: &WaveAnimation, bounds: &Bounds) -> RGBAf32 {
+    let wave_head = (dt_millis % wave.duration.as_millis() as u64) as f64 * wave.speed / 1000.0 * bounds.key_distance;
+    let wave_width = wave.light_amount * bounds.key_distance;
     let distance = if wave.two_sides {
-        wave_head - (pos_rotated - MIDPOINT).abs()
+        wave_head - position.abs()
     } else {
-        wave_head - pos_rotated
+        wave_head - position
     };
     if distance > 0.0 && distance < wave_width {
         // The key is inside the wave
         let sample_point = (distance / wave_width) as f32;
-        let effect_color = sample_animation(sample_point, &wave.animation);
-        (pos, CorsairLedColorf32 {
-            id,
-            color: alpha_compose(color, effect_color)
-        })
+        alpha_compose(color, sample_animation(sample_point, &wave.animation))
     } else {
         // Don't do nothing
-        (pos, CorsairLedColorf32 {
-            id,
-            color
-        })
+        color
     }
 }
 
-
-pub(crate) fn ripple_effect<'a>(leds: Ledsf32<'a>, dt_millis: u64, ripple: &'a RippleAnimation) -> Ledsf32<'a> {
-    Box::new(leds.map(move |key| {
-        ripple_key(key, dt_millis, ripple)
-    }))
-}
-
-pub(crate) fn ripple_key((pos, CorsairLedColorf32 {id, color}): LedInfof32, dt_millis: u64, ripple: &RippleAnimation) -> LedInfof32 {
-    const MIDPOINT_X: f64 = 200.0;
-    const MIDPOINT_Y: f64 = 100.0;
-    let ripple_head = (dt_millis % ripple.duration.as_millis() as u64) as f64 * ripple.speed / 1000.0 * LED_DISTANCE;
-    let ripple_width = ripple.light_amount * LED_DISTANCE;
-    let pos = (pos.0 - MIDPOINT_X, pos.1 - MIDPOINT_Y);
-    let d = f64::sqrt(f64::powi(pos.0, 2) + f64::powi(pos.1, 2));
-    let distance = ripple_head - d;
+/// `distance` is how far the LED is from where the ripple starts
+pub(crate) fn ripple_key(color: RGBAf32, distance: f64, dt_millis: u64, ripple: &RippleAnimation, bounds: &Bounds) -> RGBAf32 {
+    let ripple_head = (dt_millis % ripple.duration.as_millis() as u64) as f64 * ripple.speed / 1000.0 * bounds.key_distance;
+    let ripple_width = ripple.light_amount * bounds.key_distance;
+    let distance = ripple_head - distance;
 
     if distance > 0.0 && distance < ripple_width {
         // The key is inside the ripple
         let sample_point = (distance / ripple_width) as f32;
-        let effect_color = sample_animation(sample_point, &ripple.animation);
-        (pos, CorsairLedColorf32 {
-            id,
-            color: alpha_compose(color, effect_color)
-        })
+        alpha_compose(color, sample_animation(sample_point, &ripple.animation))
     } else {
         // Don't do nothing
-        (pos, CorsairLedColorf32 {
-            id,
-            color
-        })
+        color
+    }
+}
+
+pub(crate) fn flash_key(color: RGBAf32, led: CorsairLedLuid, dt_millis: u64, flash: &FlashAnimation, presses: &[Press]) -> RGBAf32 {
+    let duration = flash.duration.as_millis() as u64;
+    // A new press of the key restarts the flash
+    let last_press = presses.iter()
+        .rev()
+        .find(|press| press.led == led && dt_millis - press.time_millis < duration);
+    match last_press {
+        Some(press) => {
+            let sample_point = (dt_millis - press.time_millis) as f32 / duration as f32;
+            alpha_compose(color, sample_animation(sample_point, &flash.animation))
+        },
+        None => color,
+    }
+}
+
+pub(crate) fn key_ripple_key(mut color: RGBAf32, pos: (f64, f64), dt_millis: u64, ripple: &KeyRippleAnimation, presses: &[Press], bounds: &Bounds) -> RGBAf32 {
+    let ripple_width = ripple.light_amount * bounds.key_distance;
+    for press in presses {
+        let ripple_head = (dt_millis - press.time_millis) as f64 * ripple.speed / 1000.0 * bounds.key_distance;
+        let d = f64::sqrt(f64::powi(pos.0 - press.pos.0, 2) + f64::powi(pos.1 - press.pos.1, 2));
+        let distance = ripple_head - d;
+        if distance > 0.0 && distance < ripple_width {
+            // The key is inside the ripple of this press
+            let sample_point = (distance / ripple_width) as f32;
+            color = alpha_compose(color, sample_animation(sample_point, &ripple.animation));
+        }
     }
+
+    color
 }
 
-pub(crate) fn colorchange_effect<'a>(leds: Ledsf32<'a>, dt_millis: u64, colorchange: &'a ColorChangeAnimation) -> Ledsf32<'a> {
-    Box::new(leds.map(move |key| {
-        colorchange_key(key, dt_millis, colorchange)
-    }))
+pub(crate) fn heat_map_key(color: RGBAf32, led: CorsairLedLuid, dt_millis: u64, heat_map: &HeatMapAnimation, presses: &[Press]) -> RGBAf32 {
+    let half_life = heat_map.half_life.as_millis() as f64;
+    // Each press adds 1, which halves every half life
+    let heat: f64 = presses.iter()
+        .filter(|press| press.led == led)
+        .map(|press| f64::powf(0.5, (dt_millis - press.time_millis) as f64 / half_life))
+        .sum();
+    let sample_point = (heat / heat_map.max_presses).min(1.0) as f32;
+    alpha_compose(color, sample_animation(sample_point, &heat_map.animation))
+}
+
+/// `load` is the current value of the metric of the gauge, between 0.0 and 1.0
+pub(crate) fn gauge_key(color: RGBAf32, pos: (f64, f64), gauge: &GaugeAnimation, load: f32, bounds: &Bounds) -> RGBAf32 {
+    let effect_color = match gauge.mode {
+        GaugeMode::Bar => {
+            // Between 0.0 and 1.0, from the left edge of the device
+            let x = (pos.0 - bounds.left + bounds.key_distance / 2.0) / (bounds.right - bounds.left + bounds.key_distance);
+            if x < load as f64 {
+                sample_animation(x.clamp(0.0, 1.0) as f32, &gauge.animation)
+            } else {
+                (0.0, 0.0, 0.0, 0.0)
+            }
+        },
+        GaugeMode::Color => sample_animation(load, &gauge.animation),
+    };
+    alpha_compose(color, effect_color)
 }
 
-pub(crate) fn colorchange_key((pos, CorsairLedColorf32 {id, color}): LedInfof32, dt_millis: u64, colorchange: &ColorChangeAnimation) -> LedInfof32 {
-    let sample_point = dt_millis as f32 / colorchange.duration.as_millis() as f32;
-    let effect_color = sample_animation(sample_point, &colorchange.animation);
-    (pos, CorsairLedColorf32 {
-        id,
-        color: alpha_compose(color, effect_color)
-    })
+/// The same for every LED, so it is computed once per frame
+pub(crate) fn colorchange_color(dt_millis: u64, colorchange: &ColorChangeAnimation) -> RGBAf32 {
+    let duration = colorchange.duration.as_millis() as u64;
+    let cycle = dt_millis / duration;
+    let progress = (dt_millis % duration) as f32 / duration as f32;
+    let sample_point = match colorchange.mode {
+        ColorChangeMode::Loop => progress,
+        ColorChangeMode::PingPong if cycle % 2 == 0 => progress,
+        ColorChangeMode::PingPong => 1.0 - progress,
+        ColorChangeMode::Once if cycle == 0 => progress,
+        ColorChangeMode::Once => 1.0,
+    };
+    sample_animation(sample_point, &colorchange.animation)
 }
 
 fn sample_animation(sample_point: f32, animation: &ColorAnimation) -> RGBAf32 {
@@ -153,6 +285,10 @@ fn sample_animation(sample_point: f32, animation: &ColorAnimation) -> RGBAf32 {
         }
     }
 
+    if next_timestamp <= previous_timestamp {
+        // At (or after) the last keyframe
+        return previous_color
+    }
     linear_interpolation(previous_color, next_color, (sample_point-previous_timestamp)/(next_timestamp-previous_timestamp))
 }
 
@@ -220,3 +356,204 @@ fn alpha_compose(under_color: RGBAf32, over_color: RGBAf32) -> RGBAf32 {
 
     (out_r,out_g,out_b,out_a)
 }
+
+#[cfg(test)]
+mod tests {
+    use sis_core::{Keyframe, Metric, VirtualKey};
+
+    use crate::{corsair::frame::{render_frame, DeviceInfo, EffectStack, Frame, Layer, PressedKey}, layout::Layout, metrics::{FixedMetrics, Metrics, MetricsSource}};
+
+    use super::*;
+
+    /// From black to white, so the gray level is the sample point
+    fn black_to_white() -> ColorAnimation {
+        ColorAnimation {
+            name: "black_to_white".into(),
+            keyframes: vec![
+                Keyframe { timestamp: 0.0, color: (0.0, 0.0, 0.0, 1.0) },
+                Keyframe { timestamp: 1.0, color: (1.0, 1.0, 1.0, 1.0) },
+            ],
+        }
+    }
+
+    /// A keyboard with `effect`, where the keys are pressed at these times (in ms)
+    struct Keyboard {
+        device: DeviceInfo,
+        positions: Vec<CorsairLedPosition>,
+        effects: EffectStack,
+    }
+
+    impl Keyboard {
+        fn new(effect: Effect, presses: &[(VirtualKey, u64)]) -> Keyboard {
+            let layout = Layout::sixty_percent();
+            let mut effects = EffectStack::new();
+            effects.add_effect(Layer::everywhere(effect));
+            for (key, millis) in presses {
+                effects.press_key(PressedKey::Key(*key), Duration::from_millis(*millis))
+            }
+
+            Keyboard {
+                device: layout.device_info(),
+                positions: layout.positions(),
+                effects,
+            }
+        }
+
+        fn frame(&self, millis: u64) -> Frame {
+            render_frame(&self.device, &self.positions, &self.effects, Duration::from_millis(millis))
+        }
+
+        /// The red level of the LED of `key` at `millis`
+        fn red(&self, key: VirtualKey, millis: u64) -> u8 {
+            let led = self.device.key_led(&key).unwrap();
+            self.frame(millis).iter()
+                .find(|(id, _)| *id == led)
+                .map(|(_, (r, _, _, _))| *r)
+                .unwrap()
+        }
+
+        /// How far the farthest lit LED is from the LED of `key`, in mm
+        fn lit_radius(&self, key: VirtualKey, millis: u64) -> f64 {
+            let led = self.device.key_led(&key).unwrap();
+            let center = self.positions.iter().find(|position| position.id == led).unwrap();
+            self.frame(millis).iter()
+                .zip(self.positions.iter())
+                .filter(|((_, (r, g, b, _)), _)| (*r, *g, *b) != (0, 0, 0))
+                .map(|(_, position)| f64::hypot(position.cx - center.cx, position.cy - center.cy))
+                .fold(0.0, f64::max)
+        }
+    }
+
+    #[test]
+    fn flash_ends_after_its_duration() {
+        let flash = Effect::Flash(FlashAnimation {
+            animation: ColorAnimation {
+                name: "white".into(),
+                keyframes: vec![Keyframe { timestamp: 0.0, color: (1.0, 1.0, 1.0, 1.0) }],
+            },
+            duration: Duration::from_millis(500),
+        });
+        let keyboard = Keyboard::new(flash, &[(VirtualKey::W, 100), (VirtualKey::A, 1000)]);
+
+        assert_eq!(keyboard.red(VirtualKey::W, 50), 0);
+        assert_eq!(keyboard.red(VirtualKey::W, 100), 255);
+        assert_eq!(keyboard.red(VirtualKey::W, 599), 255);
+        assert_eq!(keyboard.red(VirtualKey::W, 600), 0);
+        // Only the key that was pressed flashes
+        assert_eq!(keyboard.red(VirtualKey::S, 300), 0);
+        assert_eq!(keyboard.red(VirtualKey::A, 1200), 255);
+        assert_eq!(keyboard.red(VirtualKey::W, 1200), 0);
+    }
+
+    #[test]
+    fn flash_restarts_when_pressed_again() {
+        let flash = Effect::Flash(FlashAnimation {
+            animation: black_to_white(),
+            duration: Duration::from_millis(1000),
+        });
+        let keyboard = Keyboard::new(flash, &[(VirtualKey::W, 0), (VirtualKey::W, 800)]);
+
+        assert!(keyboard.red(VirtualKey::W, 700) > 150);
+        assert!(keyboard.red(VirtualKey::W, 900) < 50);
+        assert!(keyboard.red(VirtualKey::W, 1500) > 150);
+    }
+
+    #[test]
+    fn key_ripple_grows_with_time() {
+        let ripple = Effect::KeyRipple(KeyRippleAnimation {
+            animation: black_to_white(),
+            speed: 10.0,
+            light_amount: 2.0,
+        });
+        let keyboard = Keyboard::new(ripple, &[(VirtualKey::G, 100)]);
+
+        assert_eq!(keyboard.lit_radius(VirtualKey::G, 100), 0.0);
+        let radii = [200, 300, 400, 500].map(|millis| keyboard.lit_radius(VirtualKey::G, millis));
+        for pair in radii.windows(2) {
+            assert!(pair[1] > pair[0], "The ripple doesn't grow: {radii:?}")
+        }
+        // 10 keys per second
+        let key_distance = Bounds::new(&keyboard.positions).key_distance;
+        assert!((radii[3] - 4.0 * key_distance).abs() < key_distance, "{radii:?}");
+    }
+
+    #[test]
+    fn heat_halves_every_half_life() {
+        let heat_map = Effect::HeatMap(HeatMapAnimation {
+            animation: black_to_white(),
+            half_life: Duration::from_millis(1000),
+            max_presses: 4.0,
+        });
+        let keyboard = Keyboard::new(heat_map, &[(VirtualKey::W, 0)]);
+
+        // 1 press out of 4
+        let heat = keyboard.red(VirtualKey::W, 0);
+        assert_eq!(heat, (0.25f32 * 255.0) as u8);
+        assert_eq!(keyboard.red(VirtualKey::W, 1000), (0.125f32 * 255.0) as u8);
+        assert_eq!(keyboard.red(VirtualKey::W, 2000), (0.0625f32 * 255.0) as u8);
+        assert_eq!(keyboard.red(VirtualKey::S, 0), 0);
+    }
+
+    #[test]
+    fn heat_saturates_at_max_presses() {
+        let heat_map = Effect::HeatMap(HeatMapAnimation {
+            animation: black_to_white(),
+            half_life: Duration::from_millis(1000),
+            max_presses: 4.0,
+        });
+        let presses = (0..8).map(|press| (VirtualKey::W, press * 10)).collect::<Vec<_>>();
+        let keyboard = Keyboard::new(heat_map, &presses);
+
+        // 5 presses
+        assert_eq!(keyboard.red(VirtualKey::W, 40), 255);
+        assert_eq!(keyboard.red(VirtualKey::W, 70), 255);
+        // 8 presses halved are still 4
+        assert_eq!(keyboard.red(VirtualKey::W, 1000), 255);
+        assert!(keyboard.red(VirtualKey::W, 2500) < 255);
+    }
+
+    /// A row of 10 keys, 20mm apart
+    fn row() -> Vec<CorsairLedPosition> {
+        (0..10).map(|id| CorsairLedPosition { id: CorsairLedLuid::new(id), cx: id as f64 * 20.0, cy: 0.0 }).collect()
+    }
+
+    /// The gray level of every key of the row, with the CPU at `cpu`
+    fn gauge_row(mode: GaugeMode, cpu: f32) -> Vec<u8> {
+        let gauge = GaugeAnimation { metric: Metric::Cpu, animation: black_to_white(), mode };
+        let load = FixedMetrics(Metrics { cpu: Some(cpu), ..Metrics::default() }).sample().load(gauge.metric);
+        let leds = row();
+        let bounds = Bounds::new(&leds);
+        leds.iter()
+            .map(|led| gauge_key((0.0, 0.0, 0.0, 1.0), (led.cx, led.cy), &gauge, load, &bounds))
+            .map(|color| rgbaf32_to_rgbau8(color).0)
+            .collect()
+    }
+
+    #[test]
+    fn gauge_bar_fills_from_the_left() {
+        assert_eq!(gauge_row(GaugeMode::Bar, 0.0), [0; 10]);
+
+        let half = gauge_row(GaugeMode::Bar, 0.5);
+        assert!(half[..5].iter().all(|gray| *gray > 0), "{half:?}");
+        assert_eq!(half[5..], [0; 5]);
+        // Colored by the animation from the left edge
+        assert!(half.windows(2).take(4).all(|pair| pair[0] < pair[1]), "{half:?}");
+
+        let full = gauge_row(GaugeMode::Bar, 1.0);
+        assert!(full.iter().all(|gray| *gray > 0), "{full:?}");
+        assert_eq!(full[..5], half[..5]);
+    }
+
+    #[test]
+    fn gauge_color_is_the_load() {
+        assert_eq!(gauge_row(GaugeMode::Color, 0.0), [0; 10]);
+        assert_eq!(gauge_row(GaugeMode::Color, 0.5), [127; 10]);
+        assert_eq!(gauge_row(GaugeMode::Color, 1.0), [255; 10]);
+    }
+
+    #[test]
+    fn unknown_metrics_are_an_empty_gauge() {
+        assert_eq!(Metrics::default().load(Metric::Cpu), 0.0);
+        assert_eq!(Metrics::default().load(Metric::Temperature), 0.0);
+    }
+}
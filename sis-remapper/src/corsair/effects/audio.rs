@@ -0,0 +1,39 @@
+//! Light that follows the sound
+
+use std::sync::Arc;
+
+use sis_core::{AudioMode, ColorAnimation, RGBAf32};
+
+use crate::audio::AudioSource;
+
+use super::{alpha_compose, sample_animation, Bounds};
+
+#[derive(Debug, Clone)]
+pub struct AudioEffect {
+    pub(crate) source: Arc<dyn AudioSource>,
+    pub(crate) animation: ColorAnimation,
+    pub(crate) bands: usize,
+    pub(crate) mode: AudioMode,
+}
+
+/// `levels` are the levels of the bands of the sound for the frame being rendered
+pub(crate) fn audio_key(color: RGBAf32, pos: (f64, f64), audio: &AudioEffect, levels: &[f32], bounds: &Bounds) -> RGBAf32 {
+    let effect_color = match audio.mode {
+        AudioMode::Bars => {
+            // Between 0.0 and 1.0, from the left edge of the device and from its bottom edge
+            let x = (pos.0 - bounds.left + bounds.key_distance / 2.0) / (bounds.right - bounds.left + bounds.key_distance);
+            let height = (bounds.bottom - pos.1 + bounds.key_distance / 2.0) / (bounds.bottom - bounds.top + bounds.key_distance);
+            let band = ((x * levels.len() as f64) as usize).min(levels.len() - 1);
+            if height < levels[band] as f64 {
+                sample_animation(height.clamp(0.0, 1.0) as f32, &audio.animation)
+            } else {
+                (0.0, 0.0, 0.0, 0.0)
+            }
+        },
+        AudioMode::Pulse => {
+            let level = levels.iter().sum::<f32>() / levels.len() as f32;
+            sample_animation(level, &audio.animation)
+        },
+    };
+    alpha_compose(color, effect_color)
+}
@@ -0,0 +1,100 @@
+//! How the color of an effect is combined with the color of the effects below it.
+//! Only colors, so every mode can be checked without rendering anything
+
+use sis_core::{BlendMode, RGBAf32};
+
+use super::alpha_compose;
+
+/// The color of `over` (an effect) on top of `under` (the effects below it)
+pub(crate) fn blend(under: RGBAf32, over: RGBAf32, mode: BlendMode) -> RGBAf32 {
+    let channel: fn(f32, f32) -> f32 = match mode {
+        BlendMode::Normal => return alpha_compose(under, over),
+        BlendMode::Replace => return over,
+        BlendMode::Add => |under, over| (under + over).min(1.0),
+        BlendMode::Multiply => |under, over| under * over,
+        BlendMode::Screen => |under, over| 1.0 - (1.0 - under) * (1.0 - over),
+        BlendMode::Overlay => |under, over| if under <= 0.5 {
+            2.0 * under * over
+        } else {
+            1.0 - 2.0 * (1.0 - under) * (1.0 - over)
+        },
+        BlendMode::Max => f32::max,
+        BlendMode::Min => f32::min,
+    };
+
+    let (u_r, u_g, u_b, u_a) = under;
+    let (o_r, o_g, o_b, o_a) = over;
+    // The more transparent the effect is, the less it changes the color below
+    let mix = |under: f32, over: f32| under + (channel(under, over) - under) * o_a;
+    (mix(u_r, o_r), mix(u_g, o_g), mix(u_b, o_b), o_a + u_a * (1.0 - o_a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNDER: RGBAf32 = (0.2, 0.5, 0.8, 1.0);
+    const OVER: RGBAf32 = (0.6, 0.5, 0.4, 1.0);
+    const TRANSPARENT: RGBAf32 = (0.0, 0.0, 0.0, 0.0);
+
+    #[track_caller]
+    fn assert_color(actual: RGBAf32, expected: RGBAf32) {
+        let (a, e) = (<[f32; 4]>::from(actual), <[f32; 4]>::from(expected));
+        assert!(a.iter().zip(e).all(|(a, e)| (a - e).abs() < 1e-6), "{actual:?} != {expected:?}");
+    }
+
+    #[test]
+    fn normal() {
+        assert_color(blend(UNDER, OVER, BlendMode::Normal), OVER);
+        assert_color(blend(TRANSPARENT, OVER, BlendMode::Normal), OVER);
+    }
+
+    #[test]
+    fn add_saturates() {
+        assert_color(blend(UNDER, OVER, BlendMode::Add), (0.8, 1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn multiply() {
+        assert_color(blend(UNDER, OVER, BlendMode::Multiply), (0.12, 0.25, 0.32, 1.0));
+    }
+
+    #[test]
+    fn screen() {
+        assert_color(blend(UNDER, OVER, BlendMode::Screen), (0.68, 0.75, 0.88, 1.0));
+    }
+
+    #[test]
+    fn overlay_multiplies_darks_and_screens_lights() {
+        assert_color(blend(UNDER, OVER, BlendMode::Overlay), (0.24, 0.5, 0.76, 1.0));
+    }
+
+    #[test]
+    fn max_and_min() {
+        assert_color(blend(UNDER, OVER, BlendMode::Max), (0.6, 0.5, 0.8, 1.0));
+        assert_color(blend(UNDER, OVER, BlendMode::Min), (0.2, 0.5, 0.4, 1.0));
+    }
+
+    #[test]
+    fn replace_ignores_what_is_below() {
+        assert_color(blend(UNDER, OVER, BlendMode::Replace), OVER);
+        assert_color(blend(TRANSPARENT, OVER, BlendMode::Replace), OVER);
+        // Even a transparent effect replaces the color below
+        assert_color(blend(UNDER, TRANSPARENT, BlendMode::Replace), TRANSPARENT);
+    }
+
+    #[test]
+    fn partial_alpha_only_goes_half_way() {
+        let half = (0.6, 0.5, 0.4, 0.5);
+        assert_color(blend(UNDER, half, BlendMode::Normal), (0.4, 0.5, 0.6, 1.0));
+        assert_color(blend(UNDER, half, BlendMode::Add), (0.5, 0.75, 0.9, 1.0));
+        assert_color(blend(UNDER, half, BlendMode::Multiply), (0.16, 0.375, 0.56, 1.0));
+    }
+
+    #[test]
+    fn transparent_effects_change_nothing() {
+        for mode in [BlendMode::Normal, BlendMode::Add, BlendMode::Multiply, BlendMode::Screen, BlendMode::Overlay, BlendMode::Max, BlendMode::Min] {
+            assert_color(blend(UNDER, TRANSPARENT, mode), UNDER);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Images (PNG or animated GIF) shown on the LEDs
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use sis_core::{rgbau8_to_rgbaf32, ImageFit, RGBAf32};
+
+use super::{alpha_compose, Bounds};
+
+/// GIFs without a delay between their frames are shown like browsers show them
+const DEFAULT_GIF_DELAY_MILLIS: u64 = 100;
+
+/// A decoded image, every frame has the size of the image
+#[derive(Debug)]
+pub struct Image {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) fit: ImageFit,
+    pub(crate) frames: Vec<ImageFrame>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ImageFrame {
+    /// Row by row, from the top left pixel
+    pub(crate) pixels: Vec<RGBAf32>,
+    pub(crate) delay_millis: u64,
+}
+
+impl Image {
+    /// Loads a GIF if the path ends with `.gif`, otherwise a PNG
+    pub(crate) fn load(path: &Path, fit: ImageFit) -> std::io::Result<Image> {
+        let is_gif = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+        let image = if is_gif {
+            load_gif(path, fit)?
+        } else {
+            load_png(path, fit)?
+        };
+        if image.width == 0 || image.height == 0 || image.frames.is_empty() {
+            return Err(std::io::Error::other("empty image"))
+        }
+
+        Ok(image)
+    }
+
+    /// How long it takes to show every frame once, 0 for still images
+    pub(crate) fn duration_millis(&self) -> u64 {
+        self.frames.iter().map(|frame| frame.delay_millis).sum()
+    }
+
+    /// The frame shown `dt_millis` after the effect started, GIFs loop forever
+    fn frame(&self, dt_millis: u64) -> &ImageFrame {
+        let duration = self.duration_millis();
+        if duration == 0 {
+            return &self.frames[0]
+        }
+        let mut t = dt_millis % duration;
+        for frame in self.frames.iter() {
+            if t < frame.delay_millis {
+                return frame
+            }
+            t -= frame.delay_millis;
+        }
+
+        &self.frames[self.frames.len() - 1]
+    }
+
+    /// The pixel under `pos`, `None` if the image doesn't cover it
+    fn pixel(&self, dt_millis: u64, pos: (f64, f64), bounds: &Bounds) -> Option<RGBAf32> {
+        // LEDs are at the center of their key, the image goes to the edges of the keys
+        let device_width = bounds.right - bounds.left + bounds.key_distance;
+        let device_height = bounds.bottom - bounds.top + bounds.key_distance;
+        let (width, height) = (self.width as f64, self.height as f64);
+        // Image pixels per device unit
+        let (scale_x, scale_y) = match self.fit {
+            ImageFit::Cover => {
+                let scale = f64::min(width / device_width, height / device_height);
+                (scale, scale)
+            },
+            ImageFit::Contain => {
+                let scale = f64::max(width / device_width, height / device_height);
+                (scale, scale)
+            },
+            ImageFit::Stretch => (width / device_width, height / device_height),
+        };
+        let (center_x, center_y) = bounds.center();
+        let x = width / 2.0 + (pos.0 - center_x) * scale_x;
+        let y = height / 2.0 + (pos.1 - center_y) * scale_y;
+        if x < 0.0 || y < 0.0 || x >= width || y >= height {
+            return None
+        }
+
+        Some(self.frame(dt_millis).pixels[y as usize * self.width + x as usize])
+    }
+}
+
+pub(crate) fn image_key(color: RGBAf32, pos: (f64, f64), dt_millis: u64, image: &Image, bounds: &Bounds) -> RGBAf32 {
+    match image.pixel(dt_millis, pos, bounds) {
+        Some(pixel) => alpha_compose(color, pixel),
+        None => color,
+    }
+}
+
+fn load_png(path: &Path, fit: ImageFit) -> std::io::Result<Image> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(std::io::Error::other)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(std::io::Error::other)?;
+    let bytes = &buffer[..info.buffer_size()];
+    let pixels = match info.color_type {
+        png::ColorType::Grayscale => bytes.iter().map(|&v| rgbau8_to_rgbaf32((v, v, v, 255))).collect(),
+        png::ColorType::GrayscaleAlpha => bytes.chunks_exact(2).map(|p| rgbau8_to_rgbaf32((p[0], p[0], p[0], p[1]))).collect(),
+        png::ColorType::Rgb => bytes.chunks_exact(3).map(|p| rgbau8_to_rgbaf32((p[0], p[1], p[2], 255))).collect(),
+        png::ColorType::Rgba => bytes.chunks_exact(4).map(|p| rgbau8_to_rgbaf32((p[0], p[1], p[2], p[3]))).collect(),
+        png::ColorType::Indexed => return Err(std::io::Error::other("indexed colors weren't expanded")),
+    };
+
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        fit,
+        frames: vec![ImageFrame {
+            pixels,
+            delay_millis: 0,
+        }],
+    })
+}
+
+fn load_gif(path: &Path, fit: ImageFit) -> std::io::Result<Image> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(BufReader::new(File::open(path)?)).map_err(std::io::Error::other)?;
+    let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+
+    // GIF frames only draw a part of the image, on top of what the previous frames left
+    let transparent = (0.0, 0.0, 0.0, 0.0);
+    let mut canvas = vec![transparent; width * height];
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(std::io::Error::other)? {
+        let previous = canvas.clone();
+        let (left, top) = (frame.left as usize, frame.top as usize);
+        let frame_pixels = frame.buffer.chunks_exact(4).enumerate();
+        for (index, p) in frame_pixels.filter(|(_, p)| p[3] != 0) {
+            let (x, y) = (left + index % frame.width as usize, top + index / frame.width as usize);
+            if x < width && y < height {
+                canvas[y * width + x] = rgbau8_to_rgbaf32((p[0], p[1], p[2], p[3]));
+            }
+        }
+        frames.push(ImageFrame {
+            pixels: canvas.clone(),
+            delay_millis: match frame.delay {
+                0 => DEFAULT_GIF_DELAY_MILLIS,
+                // In hundredths of a second
+                delay => delay as u64 * 10,
+            },
+        });
+
+        match frame.dispose {
+            gif::DisposalMethod::Any | gif::DisposalMethod::Keep => (),
+            gif::DisposalMethod::Background => {
+                for y in top..(top + frame.height as usize).min(height) {
+                    for x in left..(left + frame.width as usize).min(width) {
+                        canvas[y * width + x] = transparent;
+                    }
+                }
+            },
+            gif::DisposalMethod::Previous => canvas = previous,
+        }
+    }
+
+    Ok(Image {
+        width,
+        height,
+        fit,
+        frames,
+    })
+}
@@ -0,0 +1,237 @@
+//! Effects computed only from the position of each LED and the time. There is no randomness,
+//! "random" values come from hashing the LED, the time and a seed.
+
+use icue_bindings::types::CorsairLedLuid;
+use sis_core::{FireAnimation, PlasmaAnimation, RainbowSpiralAnimation, StarfieldAnimation, RGBAf32};
+
+use super::{alpha_compose, sample_animation, Bounds};
+
+/// `angle` is the angle of the LED around the center of the spiral (in turns), `distance` how far it is from the center
+pub(crate) fn rainbow_spiral_key(color: RGBAf32, angle: f64, distance: f64, dt_millis: u64, spiral: &RainbowSpiralAnimation, bounds: &Bounds) -> RGBAf32 {
+    let hue = angle + distance / bounds.key_distance * spiral.twist - dt_millis as f64 / 1000.0 * spiral.speed;
+    alpha_compose(color, hue_to_rgba(hue))
+}
+
+pub(crate) fn starfield_key(color: RGBAf32, led: CorsairLedLuid, dt_millis: u64, starfield: &StarfieldAnimation) -> RGBAf32 {
+    let twinkle = starfield.twinkle.as_millis() as u64;
+    let led = hash(starfield.seed ^ hash(led.get() as u64));
+    // Every LED has its own schedule, so the stars don't all change at the same time
+    let t = dt_millis + led % twinkle;
+    let cycle = t / twinkle;
+    let progress = (t % twinkle) as f64 / twinkle as f64;
+    if unit(hash(led ^ hash(cycle))) >= starfield.density {
+        // Not a star during this cycle
+        return color
+    }
+
+    // Fades in and then out
+    let brightness = 1.0 - (2.0 * progress - 1.0).abs();
+    let (r, g, b, a) = starfield.color;
+    alpha_compose(color, (r, g, b, a * brightness as f32))
+}
+
+pub(crate) fn fire_key(color: RGBAf32, pos: (f64, f64), dt_millis: u64, fire: &FireAnimation, bounds: &Bounds) -> RGBAf32 {
+    let t = dt_millis as f64 / 1000.0;
+    let x = pos.0 / bounds.key_distance;
+    // In keys, from the bottom row
+    let height = (bounds.bottom - pos.1) / bounds.key_distance;
+    // The noise moves up, so the flames rise
+    let flicker = noise(x, height - t * fire.speed, t);
+    let heat = (1.0 - height / fire.height + flicker * 0.5).clamp(0.0, 1.0);
+    alpha_compose(color, sample_animation(heat as f32, &fire.animation))
+}
+
+pub(crate) fn plasma_key(color: RGBAf32, pos: (f64, f64), dt_millis: u64, plasma: &PlasmaAnimation, bounds: &Bounds) -> RGBAf32 {
+    let t = dt_millis as f64 / 1000.0;
+    let scale = plasma.scale * bounds.key_distance;
+    let value = noise(pos.0 / scale, pos.1 / scale, t * plasma.speed);
+    let sample_point = (value * 0.5 + 0.5).clamp(0.0, 1.0);
+    alpha_compose(color, sample_animation(sample_point as f32, &plasma.animation))
+}
+
+/// Fully saturated color, `hue` is in turns (so 0.0 and 1.0 are both red)
+fn hue_to_rgba(hue: f64) -> RGBAf32 {
+    let hue = hue.rem_euclid(1.0) * 6.0;
+    let x = (1.0 - (hue % 2.0 - 1.0).abs()) as f32;
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+
+    (r, g, b, 1.0)
+}
+
+/// Perlin noise, smooth and between about -1.0 and 1.0
+fn noise(x: f64, y: f64, z: f64) -> f64 {
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+    let corner = |dx: f64, dy: f64, dz: f64| {
+        let corner_hash = hash(hash(hash((x0 + dx) as i64 as u64) ^ (y0 + dy) as i64 as u64) ^ (z0 + dz) as i64 as u64);
+        gradient(corner_hash, fx - dx, fy - dy, fz - dz)
+    };
+    let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+
+    lerp(w,
+        lerp(v,
+            lerp(u, corner(0.0, 0.0, 0.0), corner(1.0, 0.0, 0.0)),
+            lerp(u, corner(0.0, 1.0, 0.0), corner(1.0, 1.0, 0.0))),
+        lerp(v,
+            lerp(u, corner(0.0, 0.0, 1.0), corner(1.0, 0.0, 1.0)),
+            lerp(u, corner(0.0, 1.0, 1.0), corner(1.0, 1.0, 1.0))))
+}
+
+/// Dot product of one of the 12 edge directions of a cube and (x, y, z)
+fn gradient(hash: u64, x: f64, y: f64, z: f64) -> f64 {
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// splitmix64
+fn hash(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Between 0.0 (included) and 1.0 (excluded)
+fn unit(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use icue_bindings::types::CorsairLedPosition;
+    use sis_core::{BlendMode, ColorAnimation, DevicePoint, DeviceTarget, Keyframe, Timing, RGBA};
+
+    use crate::{corsair::{effects::Effect, frame::{render_frame, EffectStack, Layer}}, layout::Layout};
+
+    use super::*;
+
+    fn black_to_white() -> ColorAnimation {
+        ColorAnimation {
+            name: "black_to_white".into(),
+            keyframes: vec![
+                Keyframe { timestamp: 0.0, color: (0.0, 0.0, 0.0, 1.0) },
+                Keyframe { timestamp: 1.0, color: (1.0, 1.0, 1.0, 1.0) },
+            ],
+        }
+    }
+
+    fn effects(effect: Effect) -> EffectStack {
+        let mut effects = EffectStack::new();
+        effects.add_effect(Layer {
+            target: DeviceTarget::All,
+            zone: None,
+            blend: BlendMode::Normal,
+            timing: Timing::default(),
+            start: Duration::ZERO,
+            effect,
+        });
+
+        effects
+    }
+
+    /// The colors of the 60% layout at `millis`, in the order of its LEDs
+    fn colors(effect: Effect, millis: u64) -> Vec<RGBA> {
+        let layout = Layout::sixty_percent();
+        render_frame(&layout.device_info(), &layout.positions(), &effects(effect), Duration::from_millis(millis)).into_iter()
+            .map(|(_, color)| color)
+            .collect()
+    }
+
+    fn starfield(seed: u64) -> Effect {
+        Effect::Starfield(StarfieldAnimation {
+            color: (1.0, 1.0, 1.0, 1.0),
+            density: 0.3,
+            twinkle: Duration::from_millis(1000),
+            seed,
+        })
+    }
+
+    /// Renders the 60% layout, and the same LEDs in reverse order with other ids.
+    /// Effects that only depend on where the LEDs are and the time give every position the same color in both
+    fn assert_only_depends_on_position(effect: Effect) {
+        let layout = Layout::sixty_percent();
+        let device = layout.device_info();
+        let positions = layout.positions();
+        let moved_ids = positions.iter()
+            .rev()
+            .map(|led| CorsairLedPosition { id: CorsairLedLuid::new(led.id.get() + 1000), ..*led })
+            .collect::<Vec<_>>();
+        let effects = effects(effect);
+        for millis in [0, 250, 1000, 5000] {
+            let t = Duration::from_millis(millis);
+            let frame = render_frame(&device, &positions, &effects, t);
+            assert_eq!(frame, render_frame(&device, &positions, &effects, t));
+            let moved_frame = render_frame(&device, &moved_ids, &effects, t);
+            for ((_, color), (_, moved_color)) in frame.iter().zip(moved_frame.iter().rev()) {
+                assert_eq!(color, moved_color, "at {millis}ms")
+            }
+        }
+        assert_ne!(render_frame(&device, &positions, &effects, Duration::ZERO), render_frame(&device, &positions, &effects, Duration::from_secs(1)), "It doesn't move");
+    }
+
+    #[test]
+    fn starfield_depends_on_the_seed() {
+        let frame = colors(starfield(1), 700);
+        assert_eq!(frame, colors(starfield(1), 700));
+        assert_ne!(frame, colors(starfield(2), 700));
+
+        let stars = frame.iter().filter(|(r, _, _, _)| *r > 0).count();
+        assert!(stars > 0 && stars < frame.len(), "{stars} stars out of {} LEDs", frame.len());
+    }
+
+    #[test]
+    fn fire_only_depends_on_position_and_time() {
+        assert_only_depends_on_position(Effect::Fire(FireAnimation {
+            animation: black_to_white(),
+            speed: 2.0,
+            height: 3.0,
+        }))
+    }
+
+    #[test]
+    fn plasma_only_depends_on_position_and_time() {
+        assert_only_depends_on_position(Effect::Plasma(PlasmaAnimation {
+            animation: black_to_white(),
+            speed: 1.0,
+            scale: 3.0,
+        }))
+    }
+
+    #[test]
+    fn spiral_only_depends_on_position_and_time() {
+        assert_only_depends_on_position(Effect::RainbowSpiral(RainbowSpiralAnimation {
+            speed: 0.5,
+            twist: 0.2,
+            center: DevicePoint::CENTER,
+        }))
+    }
+}
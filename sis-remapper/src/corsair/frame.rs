@@ -0,0 +1,457 @@
+use std::time::Duration;
+
+use icue_bindings::types::{CorsairLedLuid, CorsairLedPosition};
+use cgmath::Angle;
+use sis_core::{BlendMode, DeviceTarget, DeviceType, Placement, Repeat, Timing, VirtualKey, RGBA};
+
+use super::{compositor::Compositor, effects::{Bounds, Effect}};
+
+/// How long key presses are remembered for the effects that react to them
+const PRESS_HISTORY: Duration = Duration::from_secs(60);
+
+/// The color of every LED of a device
+pub type Frame = Vec<(CorsairLedLuid, RGBA)>;
+
+/// What effects are matched against, to know if they apply to a device
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    /// `None` if the device type is unknown, only effects for every device apply to it
+    pub type_: Option<DeviceType>,
+    pub model: String,
+    pub serial: String,
+    /// The LED of each key, for key effects
+    pub keys: Vec<(VirtualKey, CorsairLedLuid)>,
+}
+
+impl DeviceInfo {
+    pub(crate) fn is_target(&self, target: &DeviceTarget) -> bool {
+        match target {
+            DeviceTarget::All => true,
+            DeviceTarget::Type(type_) => self.type_ == Some(*type_),
+            DeviceTarget::Name(name) => *name == self.model || *name == self.serial,
+        }
+    }
+
+    pub(crate) fn key_led(&self, key: &VirtualKey) -> Option<CorsairLedLuid> {
+        self.keys.iter()
+            .find(|(device_key, _)| device_key == key)
+            .map(|(_, led_id)| *led_id)
+    }
+}
+
+/// Where every device is, so effects can flow from one device to the next
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Desk {
+    pub(crate) placements: Vec<Placement>,
+}
+
+impl Desk {
+    /// The positions of the LEDs of `device` on the desk. The first placement that targets the device is used,
+    /// devices without one are left as they are.
+    pub(crate) fn place(&self, device: &DeviceInfo, leds: &[CorsairLedPosition]) -> Vec<CorsairLedPosition> {
+        let placement = match self.placement(device) {
+            Some(placement) => placement,
+            None => return leds.to_vec(),
+        };
+        let (sin, cos) = (placement.rotation.sin() as f64, placement.rotation.cos() as f64);
+
+        leds.iter()
+            .map(|led| {
+                let (x, y) = (led.cx * placement.scale, led.cy * placement.scale);
+                CorsairLedPosition {
+                    id: led.id,
+                    cx: placement.offset.0 + x * cos - y * sin,
+                    cy: placement.offset.1 + x * sin + y * cos,
+                }
+            }).collect()
+    }
+
+    pub(crate) fn placement(&self, device: &DeviceInfo) -> Option<&Placement> {
+        self.placements.iter().find(|placement| device.is_target(&placement.target))
+    }
+
+    /// A compositor for each device, with its LEDs where the desk puts them. Effects are measured over every LED
+    /// on the desk, so a wave crosses all the devices at the same speed. Without a desk, each device is on its own
+    pub(crate) fn compositors<'a>(&self, devices: impl IntoIterator<Item=(&'a DeviceInfo, &'a [CorsairLedPosition])>) -> Vec<Compositor> {
+        let placed = devices.into_iter()
+            .map(|(device, leds)| (device, self.place(device, leds)))
+            .collect::<Vec<_>>();
+        if self.placements.is_empty() {
+            return placed.into_iter().map(|(device, leds)| Compositor::new(device, leds)).collect()
+        }
+
+        let desk_leds = placed.iter().flat_map(|(_, leds)| leds.iter().copied()).collect::<Vec<_>>();
+        let bounds = Bounds::new(&desk_leds);
+        placed.into_iter().map(|(device, leds)| Compositor::with_bounds(device, leds, bounds)).collect()
+    }
+}
+
+/// An effect, and the LEDs it is applied to
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub target: DeviceTarget,
+    /// Only the LEDs of these keys, or every LED if `None`
+    pub zone: Option<Vec<VirtualKey>>,
+    pub blend: BlendMode,
+    pub timing: Timing,
+    /// When the layer was added, like the `t` of `Compositor::render`. The timing counts from it
+    pub start: Duration,
+    pub effect: Effect,
+}
+
+impl Layer {
+    /// `effect` on every LED of every device, from the start
+    #[cfg(test)]
+    pub(crate) fn everywhere(effect: Effect) -> Layer {
+        Layer {
+            target: DeviceTarget::All,
+            zone: None,
+            blend: BlendMode::Normal,
+            timing: Timing::default(),
+            start: Duration::ZERO,
+            effect,
+        }
+    }
+
+    /// How far into its animation the effect is at `t`, `None` if it isn't playing
+    pub(crate) fn effect_time(&self, t: Duration) -> Option<Duration> {
+        let played = t.checked_sub(self.start + self.timing.delay)?;
+        if self.is_finished(t) {
+            return None
+        }
+
+        Some(played + self.timing.phase)
+    }
+
+    /// The layer won't change anymore after `t`
+    fn is_static(&self, t: Duration) -> bool {
+        t >= self.start + self.timing.delay && self.effect.is_static()
+    }
+
+    /// Effects that repeat forever (or that don't have a duration) never finish
+    fn is_finished(&self, t: Duration) -> bool {
+        match (self.timing.repeat, self.effect.duration()) {
+            (Repeat::Times(times), Some(duration)) => t >= self.start + self.timing.delay + duration * times,
+            _ => false,
+        }
+    }
+}
+
+/// A key that was pressed
+#[derive(Debug, Clone, PartialEq)]
+pub enum PressedKey {
+    Key(VirtualKey),
+    /// A key without a `VirtualKey` (like the G keys of iCUE keyboards), by the serial of its device and its LED
+    Led { serial: String, led: CorsairLedLuid },
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyPress {
+    pub key: PressedKey,
+    /// Time since the effects started, like the `t` of `Compositor::render`
+    pub time: Duration,
+}
+
+/// Every effect that is currently applied, in the order they are applied
+#[derive(Debug, Clone, Default)]
+pub struct EffectStack {
+    pub effects: Vec<Layer>,
+    /// Applied after every effect in `effects`, only to the LED of their key
+    pub key_effects: Vec<(VirtualKey, Layer)>,
+    /// Recent key presses, oldest first. They are kept when the effects change
+    pub presses: Vec<KeyPress>,
+    /// The effects that were applied before, while they fade into the current ones
+    pub fading_out: Option<Box<FadeOut>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FadeOut {
+    pub effects: Vec<Layer>,
+    pub key_effects: Vec<(VirtualKey, Layer)>,
+    /// Like the `t` of `Compositor::render`
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+impl FadeOut {
+    pub(crate) fn is_finished(&self, t: Duration) -> bool {
+        t >= self.start + self.duration
+    }
+}
+
+impl EffectStack {
+    pub fn new() -> EffectStack {
+        EffectStack::default()
+    }
+
+    pub fn add_effect(&mut self, layer: Layer) {
+        self.effects.push(layer)
+    }
+
+    pub fn add_key_effect(&mut self, key: VirtualKey, layer: Layer) {
+        self.key_effects.push((key, layer))
+    }
+
+    /// Removes the effects that finished playing at `t`, and the effects that faded out
+    pub(crate) fn remove_finished(&mut self, t: Duration) {
+        self.effects.retain(|layer| !layer.is_finished(t));
+        self.key_effects.retain(|(_, layer)| !layer.is_finished(t));
+        if self.fading_out.as_ref().is_some_and(|fade| fade.is_finished(t)) {
+            self.fading_out = None
+        }
+    }
+
+    pub(crate) fn remove_all_effects(&mut self) {
+        self.effects = Vec::new();
+        self.key_effects = Vec::new();
+    }
+
+    /// Removes every effect, they keep being rendered for `duration` while the next ones fade in.
+    /// A fade that didn't finish is cut short
+    pub(crate) fn fade_out(&mut self, t: Duration, duration: Duration) {
+        self.fading_out = Some(Box::new(FadeOut {
+            effects: std::mem::take(&mut self.effects),
+            key_effects: std::mem::take(&mut self.key_effects),
+            start: t,
+            duration,
+        }))
+    }
+
+    /// The frames after `t` stay the same, until the effects change
+    pub(crate) fn is_static(&self, t: Duration) -> bool {
+        let fading = self.fading_out.as_ref().is_some_and(|fade| !fade.is_finished(t));
+        !fading && self.effects.iter()
+            .chain(self.key_effects.iter().map(|(_, layer)| layer))
+            .all(|layer| layer.is_static(t))
+    }
+
+    /// Presses must be added in the order they happened
+    pub fn press_key(&mut self, key: PressedKey, time: Duration) {
+        self.presses.retain(|press| press.time + PRESS_HISTORY >= time);
+        self.presses.push(KeyPress { key, time })
+    }
+}
+
+/// `Compositor::render` for a single frame.
+/// It sets up a new `Compositor` every time, which is what animations should keep instead
+pub fn render_frame(device: &DeviceInfo, layout: &[CorsairLedPosition], effects: &EffectStack, t: Duration) -> Frame {
+    std::mem::take(Compositor::new(device, layout.to_vec()).render(device, effects, t))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Write, path::PathBuf};
+
+    use cgmath::Rad;
+    use sis_core::{ColorAnimation, DevicePoint, Keyframe, RippleAnimation, WaveAnimation};
+
+    use crate::layout::Layout;
+
+    use super::*;
+
+    /// When the golden frames are rendered, in ms
+    const TIMES: [u64; 3] = [0, 400, 1300];
+
+    fn red_and_blue() -> ColorAnimation {
+        ColorAnimation {
+            name: "red_and_blue".into(),
+            keyframes: vec![
+                Keyframe { timestamp: 0.0, color: (1.0, 0.0, 0.0, 1.0) },
+                Keyframe { timestamp: 0.5, color: (0.0, 0.0, 1.0, 1.0) },
+                Keyframe { timestamp: 1.0, color: (1.0, 0.0, 0.0, 1.0) },
+            ],
+        }
+    }
+
+    fn effects(effect: Effect) -> EffectStack {
+        let mut effects = EffectStack::new();
+        effects.add_effect(Layer::everywhere(effect));
+
+        effects
+    }
+
+    /// Renders `effects` on the 60% layout at every time of `TIMES`, and compares the frames with `golden/{name}.txt`.
+    /// `UPDATE_GOLDEN=1 cargo test` writes the files instead, they have to be checked by hand
+    fn check_golden(name: &str, effects: &EffectStack) {
+        let layout = Layout::sixty_percent();
+        let (device, positions) = (layout.device_info(), layout.positions());
+        let mut frames = String::new();
+        for t in TIMES {
+            writeln!(frames, "t = {t}ms").unwrap();
+            let frame = render_frame(&device, &positions, effects, Duration::from_millis(t));
+            for (led, (id, (r, g, b, a))) in layout.leds.iter().zip(frame) {
+                writeln!(frames, "{:<20} {:>3} {r:02x}{g:02x}{b:02x}{a:02x}", led.name, id.get()).unwrap()
+            }
+        }
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden").join(format!("{name}.txt"));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, frames).unwrap();
+            return
+        }
+        let expected = std::fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Couldn't read {}: {err}. Run the test with UPDATE_GOLDEN=1 to write it", path.display()));
+        for (line, (rendered, expected)) in frames.lines().zip(expected.lines()).enumerate() {
+            assert_eq!(rendered, expected, "{name}: line {} changed", line + 1)
+        }
+        assert_eq!(frames.lines().count(), expected.lines().count(), "{name}: not the same number of lines")
+    }
+
+    #[test]
+    fn static_golden() {
+        check_golden("static", &effects(Effect::Static((0.2, 0.4, 0.6, 1.0))))
+    }
+
+    #[test]
+    fn wave_golden() {
+        check_golden("wave", &effects(Effect::Wave(WaveAnimation {
+            animation: red_and_blue(),
+            duration: Duration::from_secs(3),
+            speed: 10.0,
+            rotation: Rad(0.0),
+            light_amount: 5.0,
+            two_sides: false,
+            center: DevicePoint::CENTER,
+        })))
+    }
+
+    #[test]
+    fn ripple_golden() {
+        check_golden("ripple", &effects(Effect::Ripple(RippleAnimation {
+            animation: red_and_blue(),
+            duration: Duration::from_millis(2500),
+            speed: 5.0,
+            light_amount: 3.0,
+            center: DevicePoint::CENTER,
+        })))
+    }
+
+    #[test]
+    fn render_frame_is_deterministic() {
+        let layout = Layout::sixty_percent();
+        let (device, positions) = (layout.device_info(), layout.positions());
+        let effects = effects(Effect::Ripple(RippleAnimation {
+            animation: red_and_blue(),
+            duration: Duration::from_millis(2500),
+            speed: 5.0,
+            light_amount: 3.0,
+            center: DevicePoint::CENTER,
+        }));
+        let t = Duration::from_millis(700);
+        assert_eq!(render_frame(&device, &positions, &effects, t), render_frame(&device, &positions, &effects, t))
+    }
+
+    /// A device with a row of `count` LEDs, `pitch` mm apart
+    fn row(model: &str, count: u32, pitch: f64) -> (DeviceInfo, Vec<CorsairLedPosition>) {
+        let info = DeviceInfo { type_: None, model: model.into(), serial: model.into(), keys: Vec::new() };
+        let leds = (0..count).map(|id| CorsairLedPosition { id: CorsairLedLuid::new(id), cx: id as f64 * pitch, cy: 0.0 }).collect();
+        (info, leds)
+    }
+
+    #[test]
+    fn one_wave_crosses_the_desk() {
+        let devices = [row("keyboard", 10, 20.0), row("mousemat", 10, 10.0)];
+        let desk = Desk {
+            placements: vec![Placement { target: DeviceTarget::Name("mousemat".into()), offset: (300.0, 0.0), rotation: Rad(0.0), scale: 1.0 }],
+        };
+        let mut compositors = desk.compositors(devices.iter().map(|(info, leds)| (info, leds.as_slice())));
+        let effects = effects(Effect::Wave(WaveAnimation {
+            animation: ColorAnimation {
+                name: "white".into(),
+                keyframes: vec![Keyframe { timestamp: 0.0, color: (1.0, 1.0, 1.0, 1.0) }],
+            },
+            duration: Duration::from_secs(10),
+            speed: 10.0,
+            rotation: Rad(0.0),
+            light_amount: 2.0,
+            two_sides: false,
+            center: DevicePoint::CENTER,
+        }));
+        // Where the lit LEDs are on the desk
+        let mut lit = |millis: u64| {
+            let mut lit = Vec::new();
+            for ((info, leds), compositor) in devices.iter().zip(compositors.iter_mut()) {
+                let placed = desk.place(info, leds);
+                let frame = compositor.render(info, &effects, Duration::from_millis(millis));
+                lit.extend(placed.iter().zip(frame.iter()).filter(|(_, (_, (r, _, _, _)))| *r > 0).map(|(led, _)| led.cx))
+            }
+            lit
+        };
+
+        // 10 keys (of 20mm) per second from the left of the desk, 2 keys wide
+        assert_eq!(lit(500), [80.0]);
+        assert_eq!(lit(1000), [180.0]);
+        // In the gap between the devices
+        assert!(lit(1300).is_empty());
+        // The mousemat is lit when the wave gets to it, at the same speed
+        assert_eq!(lit(1600), [300.0, 310.0]);
+        assert_eq!(lit(1800), [330.0, 340.0, 350.0]);
+    }
+
+    #[test]
+    fn zones_mask_the_effect() {
+        let layout = Layout::full_size();
+        let (device, positions) = (layout.device_info(), layout.positions());
+        let wave = Effect::Wave(WaveAnimation {
+            animation: red_and_blue(),
+            duration: Duration::from_millis(3000),
+            speed: 10.0,
+            rotation: Rad(0.0),
+            light_amount: 6.0,
+            two_sides: false,
+            center: DevicePoint::CENTER,
+        });
+        let function_row = [
+            VirtualKey::F1, VirtualKey::F2, VirtualKey::F3, VirtualKey::F4, VirtualKey::F5, VirtualKey::F6,
+            VirtualKey::F7, VirtualKey::F8, VirtualKey::F9, VirtualKey::F10, VirtualKey::F11, VirtualKey::F12,
+        ];
+        let function_leds = function_row.iter().filter_map(|key| device.key_led(key)).collect::<Vec<_>>();
+        assert_eq!(function_leds.len(), 12);
+        let everywhere = effects(wave.clone());
+        let mut zoned = everywhere.clone();
+        zoned.effects[0].zone = Some(function_row.to_vec());
+
+        let mut lit = 0;
+        for t in TIMES {
+            let t = Duration::from_millis(t);
+            let everywhere = render_frame(&device, &positions, &everywhere, t);
+            let zoned = render_frame(&device, &positions, &zoned, t);
+            for (led, color) in everywhere {
+                if function_leds.contains(&led) {
+                    assert_eq!(zoned.iter().find(|(id, _)| *id == led).unwrap().1, color, "{led:?} at {t:?}");
+                    lit += (color != (0, 0, 0, 255)) as u32;
+                }
+            }
+            assert!(zoned.iter().all(|(led, color)| function_leds.contains(led) || *color == (0, 0, 0, 255)));
+        }
+        assert!(lit > 0, "The wave never gets to the function row");
+    }
+
+    #[test]
+    fn fades_are_mixed_before_rounding() {
+        let layout = Layout::sixty_percent();
+        let (device, positions) = (layout.device_info(), layout.positions());
+        let mut effects = effects(Effect::Static((0.5, 0.0, 0.0, 1.0)));
+        effects.fade_out(Duration::ZERO, Duration::from_millis(1000));
+        effects.add_effect(Layer::everywhere(Effect::Static((0.0, 0.0, 0.0, 1.0))));
+
+        let frame = render_frame(&device, &positions, &effects, Duration::from_millis(500));
+        // Half of 0.5, and not half of 127 rounded
+        assert!(frame.iter().all(|(_, color)| *color == (63, 0, 0, 255)), "{frame:?}");
+        let frame = render_frame(&device, &positions, &effects, Duration::from_millis(1000));
+        assert!(frame.iter().all(|(_, color)| *color == (0, 0, 0, 255)), "{frame:?}");
+    }
+
+    #[test]
+    fn finished_fades_are_removed() {
+        let mut effects = effects(Effect::Static((0.5, 0.0, 0.0, 1.0)));
+        effects.fade_out(Duration::from_millis(100), Duration::from_millis(1000));
+
+        effects.remove_finished(Duration::from_millis(1099));
+        assert!(effects.fading_out.is_some());
+        assert!(!effects.is_static(Duration::from_millis(1099)));
+        effects.remove_finished(Duration::from_millis(1100));
+        assert!(effects.fading_out.is_none());
+        assert!(effects.is_static(Duration::from_millis(1100)));
+    }
+}
@@ -0,0 +1,132 @@
+//! What the iCUE and OpenRGB lighting threads share: the effects, and how they become the colors sent to each device
+
+use std::time::Instant;
+
+use config_parse::Settings;
+use icue_bindings::types::{CorsairLedColor, CorsairLedLuid, CorsairLedPosition};
+use sis_core::RGBA;
+
+use crate::scheduler::FrameScheduler;
+
+use super::{compositor::Compositor, frame::{Desk, DeviceInfo, EffectStack, Frame, Layer, PressedKey}, output::Output, CorsairMsg};
+
+/// A device of a lighting thread, `backend` being what the thread needs to send it its colors
+pub(crate) struct LitDevice<T> {
+    pub(crate) backend: T,
+    pub(crate) info: DeviceInfo,
+    pub(crate) leds: Vec<CorsairLedPosition>,
+    /// Renders the LEDs where the desk puts them
+    compositor: Compositor,
+    /// The colors the device was given, empty when they aren't known
+    pub(crate) sent: Frame,
+}
+
+pub(crate) struct Lighting {
+    /// Kept when the devices are found again, so the effects go on where they were
+    start_time: Instant,
+    desk: Desk,
+    pub(crate) effects: EffectStack,
+    output: Output,
+    pub(crate) scheduler: FrameScheduler,
+    /// The last frame stays right until a message changes something
+    pub(crate) up_to_date: bool,
+}
+
+impl Lighting {
+    pub(crate) fn new() -> Lighting {
+        Lighting {
+            start_time: Instant::now(),
+            desk: Desk::default(),
+            effects: EffectStack::new(),
+            output: Output::default(),
+            scheduler: FrameScheduler::new(Settings::DEFAULT.fps),
+            up_to_date: false,
+        }
+    }
+
+    /// The devices found by a lighting thread, placed on the desk
+    pub(crate) fn devices<T>(&mut self, found: Vec<(T, DeviceInfo, Vec<CorsairLedPosition>)>) -> Vec<LitDevice<T>> {
+        self.up_to_date = false;
+        let compositors = self.desk.compositors(found.iter().map(|(_, info, leds)| (info, leds.as_slice())));
+        found.into_iter()
+            .zip(compositors)
+            .map(|((backend, info, leds), compositor)| LitDevice {
+                backend,
+                info,
+                leds,
+                compositor,
+                sent: Frame::new(),
+            }).collect()
+    }
+
+    /// Renders the next frame of every device, and gives the LEDs of each device that changed since they were sent
+    pub(crate) fn render<T>(&mut self, devices: &mut [LitDevice<T>]) -> Vec<Vec<CorsairLedColor>> {
+        let t = self.start_time.elapsed();
+        self.effects.remove_finished(t);
+        let changed = devices.iter_mut()
+            .map(|device| {
+                let frame = device.compositor.render(&device.info, &self.effects, t);
+                self.output.apply(&device.info, frame);
+                changed_leds(frame, &mut device.sent)
+            }).collect();
+        self.up_to_date = self.effects.is_static(t);
+
+        changed
+    }
+
+    pub(crate) fn press_key(&mut self, key: PressedKey) {
+        self.up_to_date = false;
+        self.effects.press_key(key, self.start_time.elapsed())
+    }
+
+    /// The messages about the effects and the settings, the others are left to the lighting thread
+    pub(crate) fn handle_msg<T>(&mut self, msg: CorsairMsg, devices: &mut [LitDevice<T>]) {
+        self.up_to_date = false;
+        match msg {
+            // Left to the lighting thread
+            CorsairMsg::SessionState(_) | CorsairMsg::MacroKeyPressed(..) => (),
+            CorsairMsg::AddEffect(layer) => self.effects.add_effect(Layer { start: self.start_time.elapsed(), ..*layer }),
+            CorsairMsg::AddKeyEffect(key, layer) => self.effects.add_key_effect(key, Layer { start: self.start_time.elapsed(), ..*layer }),
+            CorsairMsg::RemoveAllEffects => self.effects.remove_all_effects(),
+            CorsairMsg::FadeOut(duration) => self.effects.fade_out(self.start_time.elapsed(), duration),
+            CorsairMsg::SetDesk(placements) => {
+                self.desk = Desk { placements };
+                let compositors = self.desk.compositors(devices.iter().map(|device| (&device.info, device.leds.as_slice())));
+                for (device, compositor) in devices.iter_mut().zip(compositors) {
+                    device.compositor = compositor
+                }
+            },
+            CorsairMsg::SetOutput(output) => self.output = *output,
+            CorsairMsg::SetFps(fps) => self.scheduler.set_fps(fps),
+            CorsairMsg::SetBrightness(brightness) => self.output.set_brightness(brightness),
+            CorsairMsg::ChangeBrightness(change) => self.output.change_brightness(change),
+            CorsairMsg::KeyPressed(key) => self.press_key(PressedKey::Key(key)),
+        }
+    }
+}
+
+/// The LEDs of `frame` that don't have the color they had in `sent` (every LED if `sent` is empty).
+/// `sent` becomes `frame`
+fn changed_leds(frame: &Frame, sent: &mut Frame) -> Vec<CorsairLedColor> {
+    let to_led_color = |&(id, (r, g, b, a)): &(CorsairLedLuid, RGBA)| CorsairLedColor {
+        id,
+        r,
+        g,
+        b,
+        a
+    };
+    if sent.len() != frame.len() {
+        sent.clone_from(frame);
+        return frame.iter().map(to_led_color).collect()
+    }
+
+    let mut changed = Vec::new();
+    for (led, sent_led) in frame.iter().zip(sent.iter_mut()) {
+        if led != sent_led {
+            *sent_led = *led;
+            changed.push(to_led_color(led))
+        }
+    }
+
+    changed
+}
@@ -0,0 +1,58 @@
+//! The last step before the colors are sent to a device: brightness, gamma and calibration
+
+use config_parse::Settings;
+use sis_core::Calibration;
+
+use super::frame::{DeviceInfo, Frame};
+
+/// How the rendered colors become the colors sent to the devices
+#[derive(Debug, Clone)]
+pub(crate) struct Output {
+    /// Between 0.0 and 1.0
+    brightness: f32,
+    gamma: f32,
+    calibrations: Vec<Calibration>,
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output::new(&Settings::default())
+    }
+}
+
+impl Output {
+    pub(crate) fn new(settings: &Settings) -> Output {
+        Output {
+            brightness: settings.brightness as f32,
+            gamma: settings.gamma as f32,
+            calibrations: settings.calibrations.clone(),
+        }
+    }
+
+    pub(crate) fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = brightness.clamp(0.0, 1.0);
+        println!("Brightness: {:.0}%", self.brightness * 100.0);
+    }
+
+    pub(crate) fn change_brightness(&mut self, change: f32) {
+        self.set_brightness(self.brightness + change)
+    }
+
+    /// Changes the colors of `frame` to the colors `device` has to show
+    pub(crate) fn apply(&self, device: &DeviceInfo, frame: &mut Frame) {
+        let (gain_r, gain_g, gain_b) = self.calibrations.iter()
+            .find(|calibration| device.is_target(&calibration.target))
+            .map(|calibration| calibration.gains)
+            .unwrap_or((1.0, 1.0, 1.0));
+        // The brightness (and the gains) scale the light of the LED, so they are applied after the gamma
+        let output = |value: u8, gain: f32| {
+            let light = (value as f32 / 255.0).powf(self.gamma) * self.brightness * gain;
+            (light.clamp(0.0, 1.0) * 255.0).round() as u8
+        };
+        for (_, (r, g, b, _)) in frame.iter_mut() {
+            *r = output(*r, gain_r);
+            *g = output(*g, gain_g);
+            *b = output(*b, gain_b);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! The connection to iCUE, or to a fake iCUE for the tests
+
+use std::{ptr::null_mut, sync::mpsc::Sender};
+
+use icue_bindings::{types::{CorsairDeviceId, CorsairDeviceType, CorsairError, CorsairEvent, CorsairKeyEventConfiguration, CorsairLedColor, CorsairLedPosition, CorsairMacroKeyId}, CorsairConfigureKeyEvent, CorsairConnect, CorsairDisconnect, CorsairGetDevices, CorsairGetLedPositions, CorsairSetLedColors, CorsairSetLedColorsBuffer, CorsairSetLedColorsFlushBufferAsync, CorsairSubscribeForEvents, CorsairUnsubscribeFromEvents};
+
+use sis_core::DeviceType;
+
+use super::{device_type, frame::DeviceInfo, keyboard_keys, CorsairMsg, G_KEYS_LED_GROUP};
+
+/// What the lighting thread needs from iCUE
+pub(crate) trait Session: Send {
+    /// Starts connecting, every state the session goes through is sent to `tx` as `CorsairMsg::SessionState`
+    fn connect(&mut self, tx: Sender<CorsairMsg>) -> Result<(), CorsairError>;
+    fn disconnect(&mut self);
+    /// Every device, with the positions of its LEDs. Only while connected
+    fn devices(&mut self) -> Result<Vec<SessionDevice>, CorsairError>;
+    /// Sends the presses of the macro keys of `devices` (which aren't seen by the hotkey handler) to `tx`
+    fn subscribe_for_events(&mut self, devices: &[SessionDevice], tx: Sender<CorsairMsg>);
+    fn set_led_colors(&mut self, device_id: &CorsairDeviceId, colors: Vec<CorsairLedColor>) -> Result<(), CorsairError>;
+    /// Like `set_led_colors`, but the colors are only shown by `flush_led_colors`
+    fn set_led_colors_buffer(&mut self, device_id: &CorsairDeviceId, colors: Vec<CorsairLedColor>) -> Result<(), CorsairError>;
+    fn flush_led_colors(&mut self) -> Result<(), CorsairError>;
+}
+
+pub(crate) struct SessionDevice {
+    pub(crate) id: CorsairDeviceId,
+    pub(crate) info: DeviceInfo,
+    pub(crate) leds: Vec<CorsairLedPosition>,
+}
+
+/// The iCUE SDK
+pub(crate) struct IcueSession;
+
+impl Session for IcueSession {
+    fn connect(&mut self, tx: Sender<CorsairMsg>) -> Result<(), CorsairError> {
+        unsafe {
+            CorsairConnect(
+                Some(Box::new(move |state, _details| {
+                    let _ = tx.send(CorsairMsg::SessionState(state));
+                })),
+                null_mut()
+            )
+        }
+    }
+
+    fn disconnect(&mut self) {
+        let _ = CorsairDisconnect();
+    }
+
+    fn devices(&mut self) -> Result<Vec<SessionDevice>, CorsairError> {
+        let mut found = Vec::new();
+        for device in unsafe { CorsairGetDevices()? } {
+            println!("Device found: {} ({}), {} LEDs", device.model.to_string_lossy(), device.serial.to_string_lossy(), device.ledCount);
+            let leds = match CorsairGetLedPositions(&device.id) {
+                Ok(leds) => leds,
+                Err(err) => {
+                    eprintln!("Couldn't get the LEDs of {:?}: {err:?}", device.model);
+                    continue
+                },
+            };
+            let keys = match device.type_ {
+                CorsairDeviceType::Keyboard => keyboard_keys(&device.id, &leds),
+                _ => Vec::new(),
+            };
+            found.push(SessionDevice {
+                id: device.id,
+                info: DeviceInfo {
+                    type_: device_type(device.type_),
+                    model: device.model.to_string_lossy().into_owned(),
+                    serial: device.serial.to_string_lossy().into_owned(),
+                    keys,
+                },
+                leds,
+            })
+        }
+
+        Ok(found)
+    }
+
+    fn subscribe_for_events(&mut self, devices: &[SessionDevice], tx: Sender<CorsairMsg>) {
+        unsafe {
+            // Events from a previous session
+            let _ = CorsairUnsubscribeFromEvents();
+            let res = CorsairSubscribeForEvents(
+                Box::new(move |event| {
+                    if let CorsairEvent::Key { device_id, key_id, is_pressed: true } = event {
+                        let _ = tx.send(CorsairMsg::MacroKeyPressed(device_id, key_id));
+                    }
+                }),
+                null_mut()
+            );
+            if let Err(err) = res {
+                eprintln!("Couldn't subscribe to iCUE events: {err:?}");
+                return
+            }
+        }
+        // iCUE only has events for macro keys, the other keys come from the hotkey handler
+        for device in devices.iter().filter(|device| device.info.type_ == Some(DeviceType::Keyboard)) {
+            let g_keys = device.leds.iter()
+                .filter(|led| led.id.get() >> 16 == G_KEYS_LED_GROUP)
+                .filter_map(|led| CorsairMacroKeyId::try_from(led.id.get() & 0xFFFF).ok());
+            for key_id in g_keys {
+                let config = CorsairKeyEventConfiguration { key_id, is_intercepted: false };
+                if let Err(err) = CorsairConfigureKeyEvent(&device.id, config) {
+                    eprintln!("Couldn't get the presses of {:?} key {key_id:?}: {err:?}", device.info.model);
+                }
+            }
+        }
+    }
+
+    fn set_led_colors(&mut self, device_id: &CorsairDeviceId, colors: Vec<CorsairLedColor>) -> Result<(), CorsairError> {
+        unsafe { CorsairSetLedColors(device_id, colors) }
+    }
+
+    fn set_led_colors_buffer(&mut self, device_id: &CorsairDeviceId, colors: Vec<CorsairLedColor>) -> Result<(), CorsairError> {
+        CorsairSetLedColorsBuffer(device_id, colors)
+    }
+
+    fn flush_led_colors(&mut self) -> Result<(), CorsairError> {
+        CorsairSetLedColorsFlushBufferAsync()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use std::sync::{mpsc::Sender, Arc, Mutex};
+
+    use icue_bindings::types::{CorsairDeviceId, CorsairError, CorsairLedColor, CorsairLedPosition, CorsairSessionState, SysCorsairError};
+
+    use crate::{corsair::{frame::DeviceInfo, CorsairMsg}, layout::Layout};
+
+    use super::{Session, SessionDevice};
+
+    /// A session for the tests: its state is whatever the test sent last, and it remembers what the lighting thread did.
+    /// Its devices are the devices of a layout
+    pub(crate) struct FakeSession {
+        devices: Vec<(CorsairDeviceId, DeviceInfo, Vec<CorsairLedPosition>)>,
+        /// Shared with the test
+        pub(crate) icue: Arc<Mutex<FakeIcue>>,
+    }
+
+    pub(crate) struct FakeIcue {
+        pub(crate) state: CorsairSessionState,
+        pub(crate) connections: u32,
+        /// How many times the devices were asked for
+        pub(crate) enumerations: u32,
+        /// Every LED color that was sent, with the model of its device
+        pub(crate) colors: Vec<(String, CorsairLedColor)>,
+    }
+
+    impl FakeSession {
+        pub(crate) fn new(layouts: &[Layout]) -> FakeSession {
+            FakeSession {
+                devices: layouts.iter()
+                    .enumerate()
+                    .map(|(index, layout)| (CorsairDeviceId::new(&format!("fake-{index}")), layout.device_info(), layout.positions()))
+                    .collect(),
+                icue: Arc::new(Mutex::new(FakeIcue {
+                    state: CorsairSessionState::Closed,
+                    connections: 0,
+                    enumerations: 0,
+                    colors: Vec::new(),
+                })),
+            }
+        }
+
+        fn check_connected(&self) -> Result<(), CorsairError> {
+            match self.icue.lock().unwrap().state {
+                CorsairSessionState::Connected => Ok(()),
+                _ => Err(CorsairError::Sys(SysCorsairError::NotConnected)),
+            }
+        }
+
+        fn model(&self, device_id: &CorsairDeviceId) -> Result<String, CorsairError> {
+            self.devices.iter()
+                .find(|(id, _, _)| id == device_id)
+                .map(|(_, info, _)| info.model.clone())
+                .ok_or(CorsairError::Sys(SysCorsairError::DeviceNotFound))
+        }
+    }
+
+    impl Session for FakeSession {
+        fn connect(&mut self, _tx: Sender<CorsairMsg>) -> Result<(), CorsairError> {
+            self.icue.lock().unwrap().connections += 1;
+
+            Ok(())
+        }
+
+        fn disconnect(&mut self) {}
+
+        fn devices(&mut self) -> Result<Vec<SessionDevice>, CorsairError> {
+            self.check_connected()?;
+            self.icue.lock().unwrap().enumerations += 1;
+
+            Ok(self.devices.iter()
+                .map(|(id, info, leds)| SessionDevice {
+                    id: id.clone(),
+                    info: info.clone(),
+                    leds: leds.clone(),
+                }).collect())
+        }
+
+        fn subscribe_for_events(&mut self, _devices: &[SessionDevice], _tx: Sender<CorsairMsg>) {}
+
+        fn set_led_colors(&mut self, device_id: &CorsairDeviceId, colors: Vec<CorsairLedColor>) -> Result<(), CorsairError> {
+            self.check_connected()?;
+            let model = self.model(device_id)?;
+            self.icue.lock().unwrap().colors.extend(colors.into_iter().map(|color| (model.clone(), color)));
+
+            Ok(())
+        }
+
+        fn set_led_colors_buffer(&mut self, device_id: &CorsairDeviceId, colors: Vec<CorsairLedColor>) -> Result<(), CorsairError> {
+            self.set_led_colors(device_id, colors)
+        }
+
+        fn flush_led_colors(&mut self) -> Result<(), CorsairError> {
+            self.check_connected()
+        }
+    }
+}
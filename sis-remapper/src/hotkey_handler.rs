@@ -1,10 +1,10 @@
-use std::{sync::mpsc::{self, Sender}, time::{Duration, Instant}};
+use std::{sync::mpsc::{self, Sender}, time::Duration};
 
-use config_parse::{get_config, Action, Config, Macro, Profile};
-use sis_core::{ColorAnimation, VirtualKey};
-use windows::Win32::UI::Input::KeyboardAndMouse::*;
+use config_parse::{get_config, Action, Config, Macro, Profile, Settings};
+use sis_core::{ColorAnimation, Placement, VirtualKey};
+use windows::Win32::{Foundation::{HINSTANCE, LPARAM, LRESULT, WPARAM}, UI::{Input::KeyboardAndMouse::*, WindowsAndMessaging::{CallNextHookEx, SetWindowsHookExW, HC_ACTION, HHOOK, KBDLLHOOKSTRUCT, WH_KEYBOARD_LL, WM_KEYDOWN, WM_KEYUP, WM_SYSKEYDOWN, WM_SYSKEYUP}}};
 
-use crate::{corsair::{effects::Effect, CorsairMsg}, hotkey_handler::keys::{Input, KeyDirection}};
+use crate::{corsair::{frame::EffectStack, output::Output, profile_effects, CorsairMsg}, hotkey_handler::keys::{Input, KeyDirection}, layout::lit_key};
 
 mod keys;
 //mod macros;
@@ -12,13 +12,17 @@ mod keys;
 static mut HOTKEY_HANDLER: HotkeyHandler = HotkeyHandler::new();
 
 pub(crate) struct HotkeyHandler {
-    hotkeys: Vec<(VirtualKey, Vec<INPUT>, Option<String>)>,
+    /// The inputs sent by each hotkey, and the actions (like switching profile) done after sending them
+    hotkeys: Vec<(VirtualKey, Vec<INPUT>, Vec<Action>)>,
     current_profile: String,
-    profile_start_time: Option<Instant>,
     corsair_sender: Option<Sender<CorsairMsg>>,
     profiles: Vec<Profile>,
     macros: Vec<Macro>,
-    color_animations: Vec<ColorAnimation>
+    color_animations: Vec<ColorAnimation>,
+    desk: Vec<Placement>,
+    settings: Settings,
+    /// Keys that are down, so holding a key doesn't count as pressing it again
+    held_keys: Vec<VirtualKey>,
 }
 
 impl HotkeyHandler {
@@ -26,11 +30,13 @@ impl HotkeyHandler {
         HotkeyHandler {
             hotkeys: Vec::new(),
             current_profile: String::new(),
-            profile_start_time: None,
             corsair_sender: None,
             profiles: Vec::new(),
             macros: Vec::new(),
             color_animations: Vec::new(),
+            desk: Vec::new(),
+            settings: Settings::DEFAULT,
+            held_keys: Vec::new(),
         }
     }
 
@@ -40,11 +46,15 @@ impl HotkeyHandler {
                 profiles,
                 macros,
                 color_animations,
+                desk,
+                settings,
             } = get_config();
             HOTKEY_HANDLER.profiles = profiles;
             HOTKEY_HANDLER.macros = macros;
             HOTKEY_HANDLER.color_animations = color_animations;
-            HotkeyHandler::switch_profile("default".into());
+            HOTKEY_HANDLER.desk = desk;
+            HOTKEY_HANDLER.settings = settings;
+            HotkeyHandler::switch_profile("default".into(), Duration::ZERO);
         };
     }
 
@@ -90,26 +100,26 @@ impl HotkeyHandler {
             &HOTKEY_HANDLER.hotkeys
         };
 
-        for (hotkey, inputs, after_profile) in hotkeys {
+        for (hotkey, inputs, after_inputs) in hotkeys {
             if hotkey.to_vk().0 as i32 == id {
                 let res = unsafe { SendInput(inputs, std::mem::size_of::<INPUT>() as i32) };
                 assert_eq!(inputs.len(), res as usize);
 
-                if let Some(profile) = after_profile {
-                    HotkeyHandler::switch_profile(profile.clone())
+                for action in after_inputs.clone() {
+                    HotkeyHandler::do_action(action)
                 }
             }
         }
     }
 
-    pub(crate) fn switch_profile(profile: String) {
+    /// The lights of the previous profile fade into the ones of `profile` for `fade`
+    pub(crate) fn switch_profile(profile: String, fade: Duration) {
         println!("Switching to profile {profile:?}!");
         Self::unregister_hotkeys();
 
         let this = unsafe {
             &mut HOTKEY_HANDLER
         };
-        this.profile_start_time = Some(Instant::now());
         this.current_profile = profile;
         let mut actions = Vec::new();
         for profile in this.profiles.iter() {
@@ -123,13 +133,14 @@ impl HotkeyHandler {
         for action in actions {
             match action {
                 Action::SetHotkey { slot, macro_name } => hotkeys.push((slot, macro_name)),
-                Action::StaticColor(_) | Action::RippleEffect(_) | Action::WaveEffect(_) => (), // These will be handled later in HotkeyHandler::set_profile_effects()
+                Action::OnDevice(..) | Action::KeyEffect(..) | Action::Zone(..) => (), // These will be handled later in HotkeyHandler::set_profile_effects()
+                action if action.is_light_effect() => (),
                 _ => unimplemented!(),
             }
         }
 
         if let Some(_) = &this.corsair_sender {
-            HotkeyHandler::set_profile_effects()
+            HotkeyHandler::set_profile_effects(fade)
         }
 
         let hotkeys = hotkeys.into_iter().map(|(slot, macro_name)| {
@@ -141,17 +152,17 @@ impl HotkeyHandler {
                 }
             }
             let mut inputs = Vec::new();
-            let mut after_profile = None;
+            let mut after_inputs = Vec::new();
             for action in actions {
                 match action {
                     Action::ReleaseKey(key) => inputs.push(Input::Keyboard(key, KeyDirection::Release).into()),
                     Action::PressKey(key) => inputs.push(Input::Keyboard(key, KeyDirection::Press).into()),
-                    Action::SwitchProfile(profile) => after_profile = Some(profile),
+                    Action::SwitchProfile(..) | Action::SetBrightness(_) | Action::ChangeBrightness(_) => after_inputs.push(action),
                     _ => unimplemented!()
                 }
             }
 
-            (slot, inputs, after_profile)
+            (slot, inputs, after_inputs)
         }).collect();
 
         this.hotkeys = hotkeys;
@@ -159,50 +170,105 @@ impl HotkeyHandler {
         Self::register_hotkeys();
     }
 
-    fn change_corsair_effects(corsair_sender: &Sender<CorsairMsg>, effects: Vec<Box<Effect>>) {
+    /// The actions of a macro that aren't key presses
+    fn do_action(action: Action) {
+        let this = unsafe {
+            &HOTKEY_HANDLER
+        };
+        match action {
+            Action::SwitchProfile(profile, fade) => HotkeyHandler::switch_profile(profile, fade.unwrap_or(this.settings.profile_fade)),
+            Action::SetBrightness(brightness) => if let Some(corsair_sender) = &this.corsair_sender {
+                corsair_sender.send(CorsairMsg::SetBrightness(brightness as f32)).unwrap()
+            },
+            Action::ChangeBrightness(change) => if let Some(corsair_sender) = &this.corsair_sender {
+                corsair_sender.send(CorsairMsg::ChangeBrightness(change as f32)).unwrap()
+            },
+            _ => unimplemented!()
+        }
+    }
+
+    fn change_corsair_effects(corsair_sender: &Sender<CorsairMsg>, effects: EffectStack, fade: Duration) {
+        if !fade.is_zero() {
+            corsair_sender.send(CorsairMsg::FadeOut(fade)).unwrap();
+        }
         corsair_sender.send(CorsairMsg::RemoveAllEffects).unwrap();
-        for effect in effects {
-            corsair_sender.send(CorsairMsg::AddEffect(effect)).unwrap();
+        for layer in effects.effects {
+            corsair_sender.send(CorsairMsg::AddEffect(Box::new(layer))).unwrap();
+        }
+        for (key, layer) in effects.key_effects {
+            corsair_sender.send(CorsairMsg::AddKeyEffect(key, Box::new(layer))).unwrap();
         }
     }
 
     pub(crate) fn register_corsair(corsair_sender: mpsc::Sender<CorsairMsg>)  {
         let this = unsafe{&mut HOTKEY_HANDLER};
+        corsair_sender.send(CorsairMsg::SetDesk(this.desk.clone())).unwrap();
+        corsair_sender.send(CorsairMsg::SetOutput(Box::new(Output::new(&this.settings)))).unwrap();
+        corsair_sender.send(CorsairMsg::SetFps(this.settings.fps)).unwrap();
         this.corsair_sender = Some(corsair_sender);
-        HotkeyHandler::set_profile_effects();
+        HotkeyHandler::set_profile_effects(Duration::ZERO);
+        HotkeyHandler::install_key_hook();
+    }
+
+    /// Sees every key press (not only hotkeys), for the light effects that react to them
+    fn install_key_hook() {
+        unsafe {
+            if let Err(err) = SetWindowsHookExW(WH_KEYBOARD_LL, Some(key_hook), HINSTANCE::default(), 0) {
+                eprintln!("Couldn't install the keyboard hook, light effects won't react to key presses: {}", err.message())
+            }
+        }
     }
 
-    fn set_profile_effects() {
+    fn handle_key(vk: u16, pressed: bool) {
         let this = unsafe {
             &mut HOTKEY_HANDLER
         };
+        let key = match lit_key(vk) {
+            Some(key) => key,
+            None => return, // Can't be shown by any effect
+        };
 
-        let mut actions = Vec::new();
-        let elapsed_since_profile_switch = this.profile_start_time.unwrap().elapsed();
+        if !pressed {
+            this.held_keys.retain(|held_key| *held_key != key);
+            return
+        }
+        if this.held_keys.contains(&key) {
+            return // Key repeat
+        }
+        this.held_keys.push(key);
+        if let Some(corsair_sender) = &this.corsair_sender {
+            corsair_sender.send(CorsairMsg::KeyPressed(key)).unwrap();
+        }
+    }
+
+    fn set_profile_effects(fade: Duration) {
+        let this = unsafe {
+            &mut HOTKEY_HANDLER
+        };
+
+        let mut light_effects = EffectStack::new();
         for profile in this.profiles.iter() {
             if profile.name == this.current_profile {
-                actions = profile.one_time_actions.iter().filter(|action| {
-                    match action {
-                        Action::StaticColor(_) => true,
-                        Action::RippleEffect(effect) => effect.duration < elapsed_since_profile_switch,
-                        Action::WaveEffect(effect) => effect.duration < elapsed_since_profile_switch,
-                        _ => false, // not handled in this function
-                    }
-                }).chain(profile.loop_actions.iter()).cloned().collect();
+                // The lighting thread starts the timings of the effects when it receives them
+                light_effects = profile_effects(profile, Duration::ZERO);
                 break;
             }
         }
 
-        let mut light_effects = Vec::new();
-        for action in actions {
-            match action {
-                Action::StaticColor(color) => light_effects.push(Box::new(Effect::Static(color.into()))),
-                Action::RippleEffect(ripple) => light_effects.push(Box::new(Effect::Ripple(ripple))),
-                Action::WaveEffect(wave) => light_effects.push(Box::new(Effect::Wave(wave))),
-                _ => unreachable!(),
-            }
-        }
+        HotkeyHandler::change_corsair_effects(this.corsair_sender.as_ref().unwrap(), light_effects, fade)
+    }
+}
 
-        HotkeyHandler::change_corsair_effects(this.corsair_sender.as_ref().unwrap(), light_effects)
+/// Low level keyboard hook, it runs in the thread that installed it (the one with the message loop)
+unsafe extern "system" fn key_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code == HC_ACTION as i32 {
+        let info = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        match wparam.0 as u32 {
+            WM_KEYDOWN | WM_SYSKEYDOWN => HotkeyHandler::handle_key(info.vkCode as u16, true),
+            WM_KEYUP | WM_SYSKEYUP => HotkeyHandler::handle_key(info.vkCode as u16, false),
+            _ => (),
+        }
     }
+
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
 }
@@ -0,0 +1,347 @@
+use std::{path::Path, process::exit};
+
+use icue_bindings::types::{CorsairLedLuid, CorsairLedPosition};
+use serde::{Deserialize, Serialize};
+use sis_core::{DeviceType, VirtualKey};
+
+use crate::{arg_value, corsair::frame::DeviceInfo};
+
+/// Size of a 1u key, in the same unit (mm) iCUE uses for `CorsairLedPosition`
+pub(crate) const KEY_SIZE: f64 = 19.05;
+
+/// The physical LEDs of a device, for when we can't (or don't want to) ask the device itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Layout {
+    pub(crate) model: String,
+    /// Name of the device type, as used in the config file (`keyboard`, `mouse`...)
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub(crate) device_type: Option<String>,
+    pub(crate) leds: Vec<LayoutLed>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct LayoutLed {
+    #[serde(with = "led_id")]
+    pub(crate) id: CorsairLedLuid,
+    pub(crate) name: String,
+    /// Center of the key
+    pub(crate) cx: f64,
+    pub(crate) cy: f64,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+}
+
+impl Layout {
+    pub(crate) fn positions(&self) -> Vec<CorsairLedPosition> {
+        self.leds.iter()
+            .map(|led| CorsairLedPosition {
+                id: led.id,
+                cx: led.cx,
+                cy: led.cy,
+            }).collect()
+    }
+
+    pub(crate) fn device_info(&self) -> DeviceInfo {
+        let type_ = self.device_type.as_deref().and_then(|type_| DeviceType::try_from(type_).ok());
+        // Layouts of keyboards use the LED ids of iCUE
+        let keys = match type_ {
+            Some(DeviceType::Keyboard) => key_leds(|_, id, _| {
+                let id = CorsairLedLuid::new(id);
+                self.leds.iter().any(|led| led.id == id).then_some(id)
+            }),
+            _ => Vec::new(),
+        };
+
+        DeviceInfo {
+            type_,
+            model: self.model.clone(),
+            serial: String::new(),
+            keys,
+        }
+    }
+
+    /// A layout from positions reported by a device, which doesn't tell the size of each LED.
+    /// Each LED is assumed to be as big as the distance to its closest neighbour.
+    pub(crate) fn from_positions(model: String, device_type: Option<DeviceType>, positions: &[CorsairLedPosition], name: impl Fn(CorsairLedLuid) -> String) -> Layout {
+        let leds = positions.iter()
+            .map(|led| {
+                let size = positions.iter()
+                    .filter(|other| other.id != led.id)
+                    .map(|other| f64::max((other.cx - led.cx).abs(), (other.cy - led.cy).abs()))
+                    .filter(|distance| *distance > 0.0)
+                    .fold(KEY_SIZE, f64::min);
+                LayoutLed {
+                    id: led.id,
+                    name: name(led.id),
+                    cx: led.cx,
+                    cy: led.cy,
+                    width: size,
+                    height: size,
+                }
+            }).collect();
+
+        Layout {
+            model,
+            device_type: device_type.map(|type_| type_.name().into()),
+            leds,
+        }
+    }
+
+    /// The name of a built-in layout, or the path of a layout file (which can contain several devices)
+    pub(crate) fn load(name: &str) -> Result<Vec<Layout>, String> {
+        if let Some(layout) = Layout::builtin(name) {
+            return Ok(vec![layout])
+        }
+
+        let layouts = Layout::load_file(Path::new(name))?;
+        if layouts.is_empty() {
+            return Err(format!("{name} doesn't contain any device"))
+        }
+
+        Ok(layouts)
+    }
+
+    pub(crate) fn load_file(path: &Path) -> Result<Vec<Layout>, String> {
+        let file = std::fs::read_to_string(path).map_err(|err| format!("Couldn't read {}: {err}", path.display()))?;
+        serde_json::from_str(&file).map_err(|err| format!("Invalid layout file {}: {err}", path.display()))
+    }
+
+    pub(crate) fn save_file(path: &Path, layouts: &[Layout]) -> Result<(), String> {
+        let file = serde_json::to_string_pretty(layouts).unwrap();
+        std::fs::write(path, file).map_err(|err| format!("Couldn't write {}: {err}", path.display()))
+    }
+
+    pub(crate) fn builtin(name: &str) -> Option<Layout> {
+        match name {
+            "full_size" => Some(Layout::full_size()),
+            "tkl" => Some(Layout::tkl()),
+            "60" => Some(Layout::sixty_percent()),
+            _ => None,
+        }
+    }
+
+    /// Full size ANSI (US) keyboard, with the LED ids iCUE gives to each key
+    pub(crate) fn full_size() -> Layout {
+        Layout::from_rows("Full size ANSI keyboard", FULL_SIZE)
+    }
+
+    /// Full size without the numpad
+    pub(crate) fn tkl() -> Layout {
+        let mut layout = Layout::from_rows("TKL ANSI keyboard", FULL_SIZE);
+        layout.leds.retain(|led| led.cx < 18.25 * KEY_SIZE);
+
+        layout
+    }
+
+    /// TKL without the function row, the navigation keys and the arrows
+    pub(crate) fn sixty_percent() -> Layout {
+        let mut layout = Layout::from_rows("60% ANSI keyboard", FULL_SIZE);
+        layout.leds.retain(|led| led.cx < 15.0 * KEY_SIZE && led.cy > 1.5 * KEY_SIZE);
+        for led in layout.leds.iter_mut() {
+            led.cy -= 1.5 * KEY_SIZE
+        }
+
+        layout
+    }
+
+    /// Name of the key of a full size keyboard with this LED id
+    pub(crate) fn key_name(id: CorsairLedLuid) -> Option<&'static str> {
+        FULL_SIZE.iter()
+            .flat_map(|(_, keys)| keys.iter())
+            .find(|(_, key_id, _, _)| *key_id != 0 && *key_id == id.get())
+            .map(|(name, _, _, _)| *name)
+    }
+
+    fn from_rows(model: &str, rows: &[(f64, &[BuiltinKey])]) -> Layout {
+        let mut leds = Vec::new();
+        for (row_y, keys) in rows {
+            let mut x = 0.0;
+            for (name, id, width, height) in keys.iter() {
+                if *id != 0 {
+                    leds.push(LayoutLed {
+                        id: CorsairLedLuid::new(*id),
+                        name: name.to_string(),
+                        cx: (x + width / 2.0) * KEY_SIZE,
+                        cy: (row_y + height / 2.0) * KEY_SIZE,
+                        width: width * KEY_SIZE,
+                        height: height * KEY_SIZE,
+                    })
+                }
+                x += width;
+            }
+        }
+
+        Layout {
+            model: model.into(),
+            device_type: Some(DeviceType::Keyboard.name().into()),
+            leds,
+        }
+    }
+}
+
+/// The LED of every key, as found by `led` from the key, its iCUE LED id and its OpenRGB LED name
+pub(crate) fn key_leds(mut led: impl FnMut(VirtualKey, u32, &str) -> Option<CorsairLedLuid>) -> Vec<(VirtualKey, CorsairLedLuid)> {
+    KEYS.iter()
+        .filter_map(|(key, id, name)| led(*key, *id, name).map(|led| (*key, led)))
+        .collect()
+}
+
+/// The key with this Windows virtual key code, if it has its own LED
+#[cfg(windows)]
+pub(crate) fn lit_key(vk: u16) -> Option<VirtualKey> {
+    KEYS.iter()
+        .map(|(key, _, _)| *key)
+        .find(|key| key.to_vk().0 == vk)
+}
+
+/// `dump-layout --output FILE [--openrgb [address]]`: saves the layout of every connected device
+pub(crate) fn dump_layout(args: &[String]) {
+    let output = match arg_value(args, "--output") {
+        Some(output) => output,
+        None => {
+            eprintln!("Missing --output");
+            exit(1)
+        },
+    };
+
+    let layouts = if args.iter().any(|arg| arg == "--openrgb") {
+        let address = arg_value(args, "--openrgb").unwrap_or(crate::openrgb::DEFAULT_ADDRESS.into());
+        crate::openrgb::device_layouts(&address).map_err(|err| err.to_string())
+    } else {
+        icue_layouts()
+    };
+    let layouts = match layouts {
+        Ok(layouts) => layouts,
+        Err(err) => {
+            eprintln!("Couldn't get the layout of the devices: {err}");
+            exit(1)
+        },
+    };
+
+    for layout in layouts.iter() {
+        println!("{}: {} LEDs", layout.model, layout.leds.len());
+    }
+    if let Err(err) = Layout::save_file(Path::new(&output), &layouts) {
+        eprintln!("{err}");
+        exit(1)
+    }
+}
+
+#[cfg(windows)]
+fn icue_layouts() -> Result<Vec<Layout>, String> {
+    crate::corsair::device_layouts()
+}
+
+#[cfg(not(windows))]
+fn icue_layouts() -> Result<Vec<Layout>, String> {
+    Err("iCUE only works on windows, use --openrgb".into())
+}
+
+/// LED ids are plain numbers in layout files
+mod led_id {
+    use icue_bindings::types::CorsairLedLuid;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(id: &CorsairLedLuid, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(id.get())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CorsairLedLuid, D::Error> {
+        u32::deserialize(deserializer).map(CorsairLedLuid::new)
+    }
+}
+
+/// (key, iCUE LED id, OpenRGB LED name) of every key that can be lit on its own.
+/// The iCUE ids are the ones of an ANSI keyboard, on iCUE letters are looked up with `CorsairGetLedLuidForKeyName`
+/// instead, so they follow the layout of the keyboard.
+const KEYS: &[(VirtualKey, u32, &str)] = {
+    use VirtualKey as VK;
+    &[
+        (VK::Esc, 1, "Key: Escape"), (VK::F1, 2, "Key: F1"), (VK::F2, 3, "Key: F2"), (VK::F3, 4, "Key: F3"),
+        (VK::F4, 5, "Key: F4"), (VK::F5, 6, "Key: F5"), (VK::F6, 7, "Key: F6"), (VK::F7, 8, "Key: F7"),
+        (VK::F8, 9, "Key: F8"), (VK::F9, 10, "Key: F9"), (VK::F10, 11, "Key: F10"), (VK::F11, 12, "Key: F11"),
+        (VK::F12, 13, "Key: F12"), (VK::Oem3, 14, "Key: `"), (VK::N1, 15, "Key: 1"), (VK::N2, 16, "Key: 2"),
+        (VK::N3, 17, "Key: 3"), (VK::N4, 18, "Key: 4"), (VK::N5, 19, "Key: 5"), (VK::N6, 20, "Key: 6"),
+        (VK::N7, 21, "Key: 7"), (VK::N8, 22, "Key: 8"), (VK::N9, 23, "Key: 9"), (VK::N0, 24, "Key: 0"),
+        (VK::OemMinus, 25, "Key: -"), (VK::OemPlus, 26, "Key: ="), (VK::Backspace, 27, "Key: Backspace"),
+        (VK::Tab, 28, "Key: Tab"), (VK::Q, 29, "Key: Q"), (VK::W, 30, "Key: W"), (VK::E, 31, "Key: E"),
+        (VK::R, 32, "Key: R"), (VK::T, 33, "Key: T"), (VK::Y, 34, "Key: Y"), (VK::U, 35, "Key: U"), (VK::I, 36, "Key: I"),
+        (VK::O, 37, "Key: O"), (VK::P, 38, "Key: P"), (VK::Oem4, 39, "Key: ["), (VK::Oem6, 40, "Key: ]"),
+        (VK::CapsLock, 41, "Key: Caps Lock"), (VK::A, 42, "Key: A"), (VK::S, 43, "Key: S"), (VK::D, 44, "Key: D"),
+        (VK::F, 45, "Key: F"), (VK::G, 46, "Key: G"), (VK::H, 47, "Key: H"), (VK::J, 48, "Key: J"), (VK::K, 49, "Key: K"),
+        (VK::L, 50, "Key: L"), (VK::Oem1, 51, "Key: ;"), (VK::Oem7, 52, "Key: '"), (VK::Oem5, 53, "Key: \\ (ANSI)"),
+        (VK::Return, 54, "Key: Enter"), (VK::LShift, 55, "Key: Left Shift"), (VK::Shift, 55, "Key: Left Shift"),
+        (VK::Z, 57, "Key: Z"), (VK::X, 58, "Key: X"), (VK::C, 59, "Key: C"), (VK::V, 60, "Key: V"), (VK::B, 61, "Key: B"),
+        (VK::N, 62, "Key: N"), (VK::M, 63, "Key: M"), (VK::OemComma, 64, "Key: ,"), (VK::OemPeriod, 65, "Key: ."),
+        (VK::Oem2, 66, "Key: /"), (VK::RShift, 67, "Key: Right Shift"), (VK::LControl, 68, "Key: Left Control"),
+        (VK::Control, 68, "Key: Left Control"), (VK::LWin, 69, "Key: Left Windows"), (VK::LMenu, 70, "Key: Left Alt"),
+        (VK::Alt, 70, "Key: Left Alt"), (VK::Space, 71, "Key: Space"), (VK::RMenu, 72, "Key: Right Alt"),
+        (VK::RWin, 73, "Key: Right Windows"), (VK::Apps, 74, "Key: Menu"), (VK::RControl, 75, "Key: Right Control"),
+        (VK::PrintScreen, 84, "Key: Print Screen"), (VK::Scroll, 85, "Key: Scroll Lock"),
+        (VK::Pause, 86, "Key: Pause/Break"), (VK::Insert, 87, "Key: Insert"), (VK::Home, 88, "Key: Home"),
+        (VK::PageUp, 89, "Key: Page Up"), (VK::Delete, 90, "Key: Delete"), (VK::End, 91, "Key: End"),
+        (VK::PageDown, 92, "Key: Page Down"), (VK::ArrowUp, 93, "Key: Up Arrow"), (VK::ArrowLeft, 94, "Key: Left Arrow"),
+        (VK::ArrowDown, 95, "Key: Down Arrow"), (VK::ArrowRight, 96, "Key: Right Arrow"),
+        (VK::NumLock, 105, "Key: Num Lock"), (VK::Div, 106, "Key: Number Pad /"), (VK::Mult, 107, "Key: Number Pad *"),
+        (VK::Sub, 108, "Key: Number Pad -"), (VK::NP7, 109, "Key: Number Pad 7"), (VK::NP8, 110, "Key: Number Pad 8"),
+        (VK::NP9, 111, "Key: Number Pad 9"), (VK::Add, 112, "Key: Number Pad +"), (VK::NP4, 113, "Key: Number Pad 4"),
+        (VK::NP5, 114, "Key: Number Pad 5"), (VK::NP6, 115, "Key: Number Pad 6"), (VK::NP1, 116, "Key: Number Pad 1"),
+        (VK::NP2, 117, "Key: Number Pad 2"), (VK::NP3, 118, "Key: Number Pad 3"), (VK::NP0, 121, "Key: Number Pad 0"),
+        (VK::Dec, 122, "Key: Number Pad ."),
+    ]
+};
+
+/// (name, led id, width, height). Sizes are in keys, an id of 0 is an empty gap
+type BuiltinKey = (&'static str, u32, f64, f64);
+
+const GAP_QUARTER: BuiltinKey = ("", 0, 0.25, 1.0);
+const GAP_HALF: BuiltinKey = ("", 0, 0.5, 1.0);
+
+// LED ids are the ones of CorsairLedId_Keyboard in the iCUE SDK
+const FULL_SIZE: &[(f64, &[BuiltinKey])] = &[
+    (0.0, &[
+        ("Escape", 1, 1.0, 1.0), ("", 0, 1.0, 1.0),
+        ("F1", 2, 1.0, 1.0), ("F2", 3, 1.0, 1.0), ("F3", 4, 1.0, 1.0), ("F4", 5, 1.0, 1.0), GAP_HALF,
+        ("F5", 6, 1.0, 1.0), ("F6", 7, 1.0, 1.0), ("F7", 8, 1.0, 1.0), ("F8", 9, 1.0, 1.0), GAP_HALF,
+        ("F9", 10, 1.0, 1.0), ("F10", 11, 1.0, 1.0), ("F11", 12, 1.0, 1.0), ("F12", 13, 1.0, 1.0), GAP_QUARTER,
+        ("PrintScreen", 84, 1.0, 1.0), ("ScrollLock", 85, 1.0, 1.0), ("PauseBreak", 86, 1.0, 1.0),
+    ]),
+    (1.5, &[
+        ("GraveAccentAndTilde", 14, 1.0, 1.0),
+        ("1", 15, 1.0, 1.0), ("2", 16, 1.0, 1.0), ("3", 17, 1.0, 1.0), ("4", 18, 1.0, 1.0), ("5", 19, 1.0, 1.0),
+        ("6", 20, 1.0, 1.0), ("7", 21, 1.0, 1.0), ("8", 22, 1.0, 1.0), ("9", 23, 1.0, 1.0), ("0", 24, 1.0, 1.0),
+        ("MinusAndUnderscore", 25, 1.0, 1.0), ("EqualsAndPlus", 26, 1.0, 1.0), ("Backspace", 27, 2.0, 1.0), GAP_QUARTER,
+        ("Insert", 87, 1.0, 1.0), ("Home", 88, 1.0, 1.0), ("PageUp", 89, 1.0, 1.0), GAP_QUARTER,
+        ("NumLock", 105, 1.0, 1.0), ("KeypadSlash", 106, 1.0, 1.0), ("KeypadAsterisk", 107, 1.0, 1.0), ("KeypadMinus", 108, 1.0, 1.0),
+    ]),
+    (2.5, &[
+        ("Tab", 28, 1.5, 1.0),
+        ("Q", 29, 1.0, 1.0), ("W", 30, 1.0, 1.0), ("E", 31, 1.0, 1.0), ("R", 32, 1.0, 1.0), ("T", 33, 1.0, 1.0),
+        ("Y", 34, 1.0, 1.0), ("U", 35, 1.0, 1.0), ("I", 36, 1.0, 1.0), ("O", 37, 1.0, 1.0), ("P", 38, 1.0, 1.0),
+        ("BracketLeft", 39, 1.0, 1.0), ("BracketRight", 40, 1.0, 1.0), ("Backslash", 53, 1.5, 1.0), GAP_QUARTER,
+        ("Delete", 90, 1.0, 1.0), ("End", 91, 1.0, 1.0), ("PageDown", 92, 1.0, 1.0), GAP_QUARTER,
+        ("Keypad7", 109, 1.0, 1.0), ("Keypad8", 110, 1.0, 1.0), ("Keypad9", 111, 1.0, 1.0), ("KeypadPlus", 112, 1.0, 2.0),
+    ]),
+    (3.5, &[
+        ("CapsLock", 41, 1.75, 1.0),
+        ("A", 42, 1.0, 1.0), ("S", 43, 1.0, 1.0), ("D", 44, 1.0, 1.0), ("F", 45, 1.0, 1.0), ("G", 46, 1.0, 1.0),
+        ("H", 47, 1.0, 1.0), ("J", 48, 1.0, 1.0), ("K", 49, 1.0, 1.0), ("L", 50, 1.0, 1.0),
+        ("SemicolonAndColon", 51, 1.0, 1.0), ("ApostropheAndDoubleQuote", 52, 1.0, 1.0), ("Enter", 54, 2.25, 1.0),
+        ("", 0, 3.5, 1.0),
+        ("Keypad4", 113, 1.0, 1.0), ("Keypad5", 114, 1.0, 1.0), ("Keypad6", 115, 1.0, 1.0),
+    ]),
+    (4.5, &[
+        ("LeftShift", 55, 2.25, 1.0),
+        ("Z", 57, 1.0, 1.0), ("X", 58, 1.0, 1.0), ("C", 59, 1.0, 1.0), ("V", 60, 1.0, 1.0), ("B", 61, 1.0, 1.0),
+        ("N", 62, 1.0, 1.0), ("M", 63, 1.0, 1.0), ("CommaAndLessThan", 64, 1.0, 1.0), ("PeriodAndBiggerThan", 65, 1.0, 1.0),
+        ("SlashAndQuestionMark", 66, 1.0, 1.0), ("RightShift", 67, 2.75, 1.0), ("", 0, 1.25, 1.0),
+        ("UpArrow", 93, 1.0, 1.0), ("", 0, 1.25, 1.0),
+        ("Keypad1", 116, 1.0, 1.0), ("Keypad2", 117, 1.0, 1.0), ("Keypad3", 118, 1.0, 1.0), ("KeypadEnter", 120, 1.0, 2.0),
+    ]),
+    (5.5, &[
+        ("LeftCtrl", 68, 1.25, 1.0), ("LeftGui", 69, 1.25, 1.0), ("LeftAlt", 70, 1.25, 1.0), ("Space", 71, 6.25, 1.0),
+        ("RightAlt", 72, 1.25, 1.0), ("RightGui", 73, 1.25, 1.0), ("Application", 74, 1.25, 1.0), ("RightCtrl", 75, 1.25, 1.0), GAP_QUARTER,
+        ("LeftArrow", 94, 1.0, 1.0), ("DownArrow", 95, 1.0, 1.0), ("RightArrow", 96, 1.0, 1.0), GAP_QUARTER,
+        ("Keypad0", 121, 2.0, 1.0), ("KeypadPeriodAndDelete", 122, 1.0, 1.0),
+    ]),
+];
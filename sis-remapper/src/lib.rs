@@ -0,0 +1,110 @@
+use std::process::exit;
+
+#[cfg(windows)]
+use corsair::{init_corsair, session::IcueSession};
+#[cfg(windows)]
+use hotkey_handler::HotkeyHandler;
+use openrgb::init_openrgb;
+#[cfg(windows)]
+use windows::Win32::UI::WindowsAndMessaging::{GetMessageW, MSG, WM_HOTKEY};
+
+#[cfg(windows)]
+mod hotkey_handler;
+mod audio;
+pub mod corsair;
+mod layout;
+mod metrics;
+mod openrgb;
+mod preview;
+mod render;
+mod scheduler;
+
+/// The whole program, `main.rs` only calls this. It is a library so the benchmarks can use the compositor
+pub fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.first().map(|arg| arg.as_str()) {
+        Some("preview") => preview::preview(&args[1..]),
+        Some("render") => render::render(&args[1..]),
+        Some("dump-layout") => layout::dump_layout(&args[1..]),
+        _ => run(&args),
+    }
+}
+
+#[cfg(windows)]
+fn run(args: &[String]) {
+    init(args);
+    main_loop();
+}
+
+/// Without hotkeys, the lights of one profile can still be shown through OpenRGB
+#[cfg(not(windows))]
+fn run(args: &[String]) {
+    if !args.iter().any(|arg| arg == "--openrgb") {
+        eprintln!("Remapping keys only works on windows. Use `--openrgb` to show the lights of a profile through OpenRGB, or `preview` or `render` to see the light effects.");
+        exit(1)
+    }
+
+    let config = config_parse::get_config();
+    let profile_name = arg_value(args, "--profile").unwrap_or("default".into());
+    let profile = match config.profiles.iter().find(|profile| profile.name == profile_name) {
+        Some(profile) => profile,
+        None => {
+            eprintln!("Profile {profile_name} doesn't exist");
+            exit(1)
+        },
+    };
+    let corsair_sender = init_openrgb(arg_value(args, "--openrgb").unwrap_or(openrgb::DEFAULT_ADDRESS.into()));
+    corsair::show_profile(&corsair_sender, &config, profile);
+    loop {
+        std::thread::park()
+    }
+}
+
+#[cfg(windows)]
+fn main_loop() -> !{
+    loop {
+        unsafe {
+            let mut message = MSG::default();
+            let _ = GetMessageW(&mut message, None, WM_HOTKEY, WM_HOTKEY+1);
+            if message.message == WM_HOTKEY {
+                HotkeyHandler::handle_hotkey(message.wParam.0 as i32)
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+fn init(args: &[String]) {
+    ctrlc::set_handler(handle_ctrlc).unwrap();
+    HotkeyHandler::init();
+    // `--openrgb [address]` drives the lights through an OpenRGB SDK server instead of iCUE
+    let corsair_sender = if args.iter().any(|arg| arg == "--openrgb") {
+        init_openrgb(arg_value(args, "--openrgb").unwrap_or(openrgb::DEFAULT_ADDRESS.into()))
+    } else {
+        init_corsair(Box::new(IcueSession))
+    };
+    HotkeyHandler::register_corsair(corsair_sender);
+}
+
+/// The argument that comes after `flag`
+fn arg_value(args: &[String], flag: &str) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next()
+                .filter(|value| !value.starts_with("--"))
+                .cloned()
+        }
+    }
+
+    None
+}
+
+#[cfg(windows)]
+fn handle_ctrlc() {
+    println!("Cleaning");
+    HotkeyHandler::cleanup();
+
+    println!("Exited correctly. Goodbye");
+    exit(0)
+}
@@ -0,0 +1,146 @@
+//! How loaded the computer is, sampled by a thread of its own so rendering never waits for it
+
+use std::{path::Path, sync::Mutex, time::Duration};
+
+use sis_core::Metric;
+
+/// How often the metrics are sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Temperatures (in °C) shown as a load of 0.0 and 1.0
+const TEMPERATURE_RANGE: (f32, f32) = (30.0, 100.0);
+
+/// The last sample, `None` until sampling starts
+static LATEST: Mutex<Option<Metrics>> = Mutex::new(None);
+
+/// Every value is `None` if it couldn't be read
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Metrics {
+    /// Between 0.0 and 1.0
+    pub(crate) cpu: Option<f32>,
+    /// Between 0.0 and 1.0
+    pub(crate) memory: Option<f32>,
+    /// In °C
+    pub(crate) temperature: Option<f32>,
+}
+
+impl Metrics {
+    /// Between 0.0 and 1.0, 0.0 if the metric is unknown
+    pub(crate) fn load(&self, metric: Metric) -> f32 {
+        let load = match metric {
+            Metric::Cpu => self.cpu,
+            Metric::Memory => self.memory,
+            Metric::Temperature => self.temperature
+                .map(|temperature| (temperature - TEMPERATURE_RANGE.0) / (TEMPERATURE_RANGE.1 - TEMPERATURE_RANGE.0)),
+        };
+        load.unwrap_or(0.0).clamp(0.0, 1.0)
+    }
+}
+
+pub(crate) trait MetricsSource: Send + 'static {
+    fn sample(&mut self) -> Metrics;
+}
+
+/// Starts sampling `source`, unless something is already sampled. The first sample is taken before returning
+pub(crate) fn start_sampling(mut source: impl MetricsSource) {
+    let mut latest = LATEST.lock().unwrap();
+    if latest.is_some() {
+        return
+    }
+    let metrics = source.sample();
+    if metrics == Metrics::default() {
+        eprintln!("None of the system metrics can be read here (only Linux is supported), gauges will stay empty");
+    }
+    *latest = Some(metrics);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SAMPLE_INTERVAL);
+        let metrics = source.sample();
+        *LATEST.lock().unwrap() = Some(metrics);
+    });
+}
+
+pub(crate) fn latest_metrics() -> Metrics {
+    LATEST.lock().unwrap().unwrap_or_default()
+}
+
+/// Always the same values, to see the gauges without loading the computer
+pub(crate) struct FixedMetrics(pub(crate) Metrics);
+
+impl MetricsSource for FixedMetrics {
+    fn sample(&mut self) -> Metrics {
+        self.0
+    }
+}
+
+/// Reads `/proc` and `/sys`, so it only knows the metrics on Linux. Everywhere else (Windows included)
+/// every metric is `None`, and gauges show a load of 0.0
+#[derive(Debug, Default)]
+pub(crate) struct SystemMetrics {
+    /// (idle, total) CPU time of the previous sample
+    previous_cpu_times: Option<(u64, u64)>,
+}
+
+impl MetricsSource for SystemMetrics {
+    fn sample(&mut self) -> Metrics {
+        Metrics {
+            cpu: self.cpu(),
+            memory: memory(),
+            temperature: temperature(),
+        }
+    }
+}
+
+impl SystemMetrics {
+    /// Since the previous sample, or since boot for the first one
+    fn cpu(&mut self) -> Option<f32> {
+        let stat = std::fs::read_to_string("/proc/stat").ok()?;
+        // cpu  user nice system idle iowait irq softirq steal
+        let times = stat.lines().next()?
+            .split_whitespace()
+            .skip(1)
+            .take(8)
+            .map(|time| time.parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        if times.len() < 5 {
+            return None
+        }
+        let idle = times[3] + times[4];
+        let total = times.iter().sum::<u64>();
+
+        let (previous_idle, previous_total) = self.previous_cpu_times.replace((idle, total)).unwrap_or((0, 0));
+        let total = total.saturating_sub(previous_total);
+        if total == 0 {
+            return None
+        }
+        Some(1.0 - idle.saturating_sub(previous_idle) as f32 / total as f32)
+    }
+}
+
+fn memory() -> Option<f32> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let value = |name: &str| -> Option<f32> {
+        meminfo.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))?
+            .split_whitespace()
+            .next()?
+            .parse().ok()
+    };
+    let total = value("MemTotal")?;
+    let available = value("MemAvailable")?;
+    if total == 0.0 {
+        return None
+    }
+
+    Some(1.0 - available / total)
+}
+
+/// The hottest thermal zone
+fn temperature() -> Option<f32> {
+    std::fs::read_dir(Path::new("/sys/class/thermal")).ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("thermal_zone"))
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("temp")).ok())
+        // In thousandths of °C
+        .filter_map(|temp| temp.trim().parse::<f32>().ok())
+        .map(|temp| temp / 1000.0)
+        .reduce(f32::max)
+}
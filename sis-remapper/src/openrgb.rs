@@ -0,0 +1,265 @@
+use std::{sync::mpsc::{self, Receiver, Sender}, time::{Duration, Instant}};
+
+use icue_bindings::types::{CorsairLedLuid, CorsairLedPosition};
+
+use sis_core::DeviceType;
+
+use crate::{corsair::{frame::DeviceInfo, lighting::{Lighting, LitDevice}, CorsairMsg}, layout::{key_leds, Layout, LayoutLed}, scheduler::{Pace, Wake}};
+
+use self::protocol::{ControllerData, OpenRgbClient, ZoneType};
+
+mod protocol;
+
+pub(crate) const DEFAULT_ADDRESS: &str = "127.0.0.1:6742";
+const CLIENT_NAME: &str = "sis-remapper";
+/// Distance between 2 neighbouring LEDs of a zone, in the same unit (mm) iCUE uses for `CorsairLedPosition`
+const LED_PITCH: f64 = 19.05;
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+/// How often the device list is checked while the lights don't change
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) fn init_openrgb(address: String) -> Sender<CorsairMsg> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || listener(rx, address));
+
+    tx
+}
+
+fn listener(rx: Receiver<CorsairMsg>, address: String) {
+    let mut openrgb_state = OpenRgbState::new(address);
+    loop {
+        match openrgb_state.lighting.scheduler.wait(&rx, openrgb_state.pace()) {
+            Wake::Message(msg) => openrgb_state.handle_msg(msg),
+            Wake::Frame => {
+                let start = Instant::now();
+                openrgb_state.tick();
+                openrgb_state.lighting.scheduler.frame_done(start)
+            },
+        }
+    }
+}
+
+struct Controller {
+    index: u32,
+    /// The colors sent to the server, one per LED of the controller
+    colors: Vec<(u8, u8, u8)>,
+}
+
+struct OpenRgbState {
+    address: String,
+    client: Option<OpenRgbClient>,
+    /// When to connect again, after the server couldn't be reached
+    retry_at: Option<Instant>,
+    retry_delay: Duration,
+    lighting: Lighting,
+    controllers: Vec<LitDevice<Controller>>,
+}
+
+impl OpenRgbState {
+    fn new(address: String) -> OpenRgbState {
+        OpenRgbState {
+            address,
+            client: None,
+            retry_at: None,
+            retry_delay: MIN_RETRY_DELAY,
+            lighting: Lighting::new(),
+            controllers: Vec::new(),
+        }
+    }
+
+    fn connect(&mut self) {
+        match OpenRgbClient::connect(&self.address, CLIENT_NAME) {
+            Ok(client) => {
+                println!("Connected to OpenRGB server at {}", self.address);
+                self.client = Some(client);
+                self.retry_delay = MIN_RETRY_DELAY;
+                if let Err(err) = self.setup() {
+                    self.disconnect(err)
+                }
+            },
+            Err(err) => {
+                eprintln!("Couldn't connect to OpenRGB server at {}: {err}. Retrying in {:?}", self.address, self.retry_delay);
+                // Messages are still handled while waiting
+                self.retry_at = Some(Instant::now() + self.retry_delay);
+                self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+            },
+        }
+    }
+
+    fn pace(&self) -> Pace {
+        match self.retry_at {
+            Some(retry_at) => Pace::IdleFor(retry_at.saturating_duration_since(Instant::now())),
+            // The device list still has to be checked while idle
+            None if self.lighting.up_to_date => Pace::IdleFor(IDLE_POLL_INTERVAL),
+            None => Pace::Animated,
+        }
+    }
+
+    fn disconnect(&mut self, err: std::io::Error) {
+        eprintln!("Lost connection to OpenRGB server: {err}");
+        self.client = None;
+        self.controllers = Vec::new();
+        self.lighting.up_to_date = false;
+    }
+
+    fn setup(&mut self) -> std::io::Result<()> {
+        let client = self.client.as_mut().unwrap();
+        let mut found = Vec::new();
+        for index in 0..client.controller_count()? {
+            let controller = client.controller_data(index)?;
+            println!("Device found: {} ({}), {} LEDs", controller.name, controller.serial, controller.led_names.len());
+            let info = DeviceInfo {
+                type_: device_type(controller.device_type),
+                model: controller.name.clone(),
+                serial: controller.serial.clone(),
+                keys: key_leds(|_, _, name| {
+                    controller.led_names.iter()
+                        .position(|led_name| led_name == name)
+                        .map(|index| CorsairLedLuid::new(index as u32))
+                }),
+            };
+            let colors = vec![(0, 0, 0); controller.led_names.len()];
+            found.push((Controller { index, colors }, info, led_positions(&controller)))
+        }
+        self.controllers = self.lighting.devices(found);
+
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        if self.client.is_none() {
+            if self.retry_at.is_none_or(|retry_at| retry_at <= Instant::now()) {
+                self.retry_at = None;
+                self.connect();
+            }
+            return
+        }
+
+        if let Err(err) = self.update() {
+            self.disconnect(err)
+        }
+    }
+
+    fn update(&mut self) -> std::io::Result<()> {
+        if self.client.as_mut().unwrap().poll_device_list_updated()? {
+            println!("OpenRGB device list changed");
+            self.setup()?;
+        }
+
+        let updates = self.lighting.render(&mut self.controllers);
+        let client = self.client.as_mut().unwrap();
+        for (controller, changed) in self.controllers.iter_mut().zip(updates) {
+            if changed.is_empty() {
+                continue
+            }
+            let Controller { index, colors } = &mut controller.backend;
+            for led in changed {
+                colors[led.id.get() as usize] = (led.r, led.g, led.b)
+            }
+            client.update_leds(*index, colors)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_msg(&mut self, msg: CorsairMsg) {
+        self.lighting.handle_msg(msg, &mut self.controllers)
+    }
+}
+
+/// Connects to the server just to read the LEDs of every controller
+pub(crate) fn device_layouts(address: &str) -> std::io::Result<Vec<Layout>> {
+    let mut client = OpenRgbClient::connect(address, CLIENT_NAME)?;
+    let mut layouts = Vec::new();
+    for index in 0..client.controller_count()? {
+        let controller = client.controller_data(index)?;
+        let leds = led_positions(&controller).into_iter()
+            .map(|led| LayoutLed {
+                id: led.id,
+                name: controller.led_names[led.id.get() as usize].clone(),
+                cx: led.cx,
+                cy: led.cy,
+                width: LED_PITCH,
+                height: LED_PITCH,
+            }).collect();
+        layouts.push(Layout {
+            model: controller.name,
+            device_type: device_type(controller.device_type).map(|type_| type_.name().into()),
+            leds,
+        })
+    }
+
+    Ok(layouts)
+}
+
+/// OpenRGB device types, `None` for the ones iCUE doesn't have
+fn device_type(device_type: i32) -> Option<DeviceType> {
+    Some(match device_type {
+        0 => DeviceType::Motherboard,
+        1 => DeviceType::MemoryModule,
+        2 => DeviceType::GraphicsCard,
+        3 => DeviceType::Cooler,
+        4 => DeviceType::LedController,
+        5 => DeviceType::Keyboard,
+        6 => DeviceType::Mouse,
+        7 => DeviceType::Mousemat,
+        8 => DeviceType::Headset,
+        9 => DeviceType::HeadsetStand,
+        10 => DeviceType::GameController,
+        _ => return None,
+    })
+}
+
+/// Lays out every zone of the controller one below the other.
+///
+/// Matrix zones keep their shape, linear zones become a single row and single zones a single LED.
+/// The id of each LED is its index in the controller, which is the order OpenRGB expects the colors in.
+fn led_positions(controller: &ControllerData) -> Vec<CorsairLedPosition> {
+    let mut leds = Vec::with_capacity(controller.led_names.len());
+    let mut zone_start = 0u32;
+    let mut zone_y = 0.0;
+    for zone in controller.zones.iter() {
+        let mut rows = 1;
+        match (&zone.type_, &zone.matrix) {
+            (ZoneType::Matrix, Some((height, width, map))) => {
+                rows = *height;
+                for (cell, &led) in map.iter().enumerate() {
+                    // Empty cells are `u32::MAX`, and a bad server can send any other LED that isn't in the zone
+                    let id = match zone_start.checked_add(led) {
+                        Some(id) if led < zone.leds_count => id,
+                        _ => continue,
+                    };
+                    let (row, column) = (cell / *width as usize, cell % *width as usize);
+                    leds.push(CorsairLedPosition {
+                        id: CorsairLedLuid::new(id),
+                        cx: (column as f64 + 0.5) * LED_PITCH,
+                        cy: zone_y + (row as f64 + 0.5) * LED_PITCH,
+                    })
+                }
+            },
+            _ => {
+                // No more LEDs than the controller has, whatever the zone says
+                let leds_count = zone.leds_count.min(controller.led_names.len() as u32);
+                for led in 0..leds_count {
+                    let id = match zone_start.checked_add(led) {
+                        Some(id) => id,
+                        None => break,
+                    };
+                    leds.push(CorsairLedPosition {
+                        id: CorsairLedLuid::new(id),
+                        cx: (led as f64 + 0.5) * LED_PITCH,
+                        cy: zone_y + 0.5 * LED_PITCH,
+                    })
+                }
+            },
+        }
+        zone_start = zone_start.saturating_add(zone.leds_count);
+        zone_y += rows as f64 * LED_PITCH;
+    }
+
+    // Ignore malformed matrix maps instead of indexing out of bounds when sending the colors
+    leds.retain(|led| (led.id.get() as usize) < controller.led_names.len());
+
+    leds
+}
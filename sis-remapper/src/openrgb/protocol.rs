@@ -0,0 +1,526 @@
+use std::{io::{self, ErrorKind, Read, Write}, net::{TcpStream, ToSocketAddrs}, time::Duration};
+
+// https://gitlab.com/CalcProgrammer1/OpenRGB/-/blob/master/Documentation/OpenRGBSDK.md
+const MAGIC: &[u8; 4] = b"ORGB";
+const HEADER_SIZE: usize = 16;
+/// Highest protocol version this client knows how to parse.
+/// Version 4 adds zone segments, which we don't need.
+const MAX_PROTOCOL_VERSION: u32 = 3;
+/// How long the lighting thread waits for a server that doesn't answer
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const IO_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PacketId {
+    RequestControllerCount = 0,
+    RequestControllerData = 1,
+    RequestProtocolVersion = 40,
+    SetClientName = 50,
+    DeviceListUpdated = 100,
+    UpdateLeds = 1050,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ZoneType {
+    Single,
+    Linear,
+    Matrix,
+}
+
+impl From<i32> for ZoneType {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => ZoneType::Single,
+            2 => ZoneType::Matrix,
+            _ => ZoneType::Linear,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Zone {
+    pub(crate) type_: ZoneType,
+    pub(crate) leds_count: u32,
+    /// (height, width, map). Each map cell is the index of the LED inside the zone, or `u32::MAX` if empty
+    pub(crate) matrix: Option<(u32, u32, Vec<u32>)>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ControllerData {
+    pub(crate) device_type: i32,
+    pub(crate) name: String,
+    pub(crate) serial: String,
+    pub(crate) zones: Vec<Zone>,
+    pub(crate) led_names: Vec<String>,
+}
+
+pub(crate) struct OpenRgbClient {
+    stream: TcpStream,
+    protocol_version: u32,
+    device_list_updated: bool,
+}
+
+impl OpenRgbClient {
+    pub(crate) fn connect(address: &str, client_name: &str) -> io::Result<OpenRgbClient> {
+        let stream = connect_timeout(address)?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        let mut client = OpenRgbClient {
+            stream,
+            protocol_version: 0,
+            device_list_updated: false,
+        };
+
+        let mut name = client_name.as_bytes().to_vec();
+        name.push(0);
+        client.send(0, PacketId::SetClientName, &name)?;
+
+        // Servers older than protocol version 1 never answer this request
+        client.stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        client.send(0, PacketId::RequestProtocolVersion, &MAX_PROTOCOL_VERSION.to_le_bytes())?;
+        client.protocol_version = match client.recv_reply(PacketId::RequestProtocolVersion) {
+            Ok(data) => Reader::new(&data).u32()?.min(MAX_PROTOCOL_VERSION),
+            Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => 0,
+            Err(err) => return Err(err),
+        };
+        client.stream.set_read_timeout(Some(IO_TIMEOUT))?;
+
+        Ok(client)
+    }
+
+    pub(crate) fn controller_count(&mut self) -> io::Result<u32> {
+        self.send(0, PacketId::RequestControllerCount, &[])?;
+        let data = self.recv_reply(PacketId::RequestControllerCount)?;
+        Reader::new(&data).u32()
+    }
+
+    pub(crate) fn controller_data(&mut self, index: u32) -> io::Result<ControllerData> {
+        let request = if self.protocol_version > 0 {
+            self.protocol_version.to_le_bytes().to_vec()
+        } else {
+            Vec::new()
+        };
+        self.send(index, PacketId::RequestControllerData, &request)?;
+        let data = self.recv_reply(PacketId::RequestControllerData)?;
+
+        parse_controller_data(&data, self.protocol_version)
+    }
+
+    /// Sets the color of every LED of the controller. `colors` must be in the same order the server sent the LEDs
+    pub(crate) fn update_leds(&mut self, index: u32, colors: &[(u8, u8, u8)]) -> io::Result<()> {
+        let data_size = 4 + 2 + colors.len() * 4;
+        let mut data = Vec::with_capacity(data_size);
+        data.extend_from_slice(&(data_size as u32).to_le_bytes());
+        data.extend_from_slice(&(colors.len() as u16).to_le_bytes());
+        for (r, g, b) in colors {
+            data.extend_from_slice(&[*r, *g, *b, 0]);
+        }
+
+        self.send(index, PacketId::UpdateLeds, &data)
+    }
+
+    /// Reads any packet the server sent on its own. Returns true if the controller list changed since the last call.
+    ///
+    /// Also the way a closed server is detected, since we never wait for replies while rendering.
+    pub(crate) fn poll_device_list_updated(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut peek = [0; 1];
+        let pending = match self.stream.peek(&mut peek) {
+            Ok(0) => Err(io::Error::new(ErrorKind::UnexpectedEof, "OpenRGB server closed the connection")),
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        };
+        self.stream.set_nonblocking(false)?;
+
+        if pending? {
+            let (_, packet_id, _) = self.recv()?;
+            if packet_id == PacketId::DeviceListUpdated as u32 {
+                self.device_list_updated = true
+            }
+        }
+
+        Ok(std::mem::take(&mut self.device_list_updated))
+    }
+
+    fn send(&mut self, device: u32, packet_id: PacketId, data: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(HEADER_SIZE + data.len());
+        packet.extend_from_slice(MAGIC);
+        packet.extend_from_slice(&device.to_le_bytes());
+        packet.extend_from_slice(&(packet_id as u32).to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        packet.extend_from_slice(data);
+
+        self.stream.write_all(&packet)
+    }
+
+    fn recv(&mut self) -> io::Result<(u32, u32, Vec<u8>)> {
+        let mut header = [0; HEADER_SIZE];
+        self.stream.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Invalid OpenRGB packet header"))
+        }
+        let mut reader = Reader::new(&header[4..]);
+        let device = reader.u32()?;
+        let packet_id = reader.u32()?;
+        let size = reader.u32()?;
+
+        let mut data = vec![0; size as usize];
+        self.stream.read_exact(&mut data)?;
+
+        Ok((device, packet_id, data))
+    }
+
+    fn recv_reply(&mut self, packet_id: PacketId) -> io::Result<Vec<u8>> {
+        loop {
+            let (_, id, data) = self.recv()?;
+            if id == packet_id as u32 {
+                return Ok(data)
+            } else if id == PacketId::DeviceListUpdated as u32 {
+                self.device_list_updated = true
+            }
+        }
+    }
+}
+
+/// Like `TcpStream::connect`, but gives up on each address after `CONNECT_TIMEOUT`
+fn connect_timeout(address: &str) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(ErrorKind::InvalidInput, format!("{address} isn't an address"));
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+
+    Err(last_err)
+}
+
+fn parse_controller_data(data: &[u8], protocol_version: u32) -> io::Result<ControllerData> {
+    let mut reader = Reader::new(data);
+    let _data_size = reader.u32()?;
+    let device_type = reader.i32()?;
+    let name = reader.string()?;
+    if protocol_version >= 1 {
+        let _vendor = reader.string()?;
+    }
+    let _description = reader.string()?;
+    let _version = reader.string()?;
+    let serial = reader.string()?;
+    let _location = reader.string()?;
+
+    let num_modes = reader.u16()?;
+    let _active_mode = reader.i32()?;
+    for _ in 0..num_modes {
+        let _name = reader.string()?;
+        // value, flags, speed_min, speed_max
+        reader.skip(4 * 4)?;
+        if protocol_version >= 3 {
+            // brightness_min, brightness_max
+            reader.skip(4 * 2)?;
+        }
+        // colors_min, colors_max, speed
+        reader.skip(4 * 3)?;
+        if protocol_version >= 3 {
+            // brightness
+            reader.skip(4)?;
+        }
+        // direction, color_mode
+        reader.skip(4 * 2)?;
+        let num_colors = reader.u16()?;
+        reader.skip(4 * num_colors as usize)?;
+    }
+
+    let num_zones = reader.u16()?;
+    let mut zones = Vec::with_capacity(num_zones as usize);
+    for _ in 0..num_zones {
+        let _name = reader.string()?;
+        let type_ = reader.i32()?.into();
+        let _leds_min = reader.u32()?;
+        let _leds_max = reader.u32()?;
+        let leds_count = reader.u32()?;
+        let matrix_len = reader.u16()?;
+        let matrix = if matrix_len > 0 {
+            let height = reader.u32()?;
+            let width = reader.u32()?;
+            let cells = height.checked_mul(width)
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "OpenRGB matrix map too big"))?;
+            // The size comes from the server, the map can't be longer than the data it is read from
+            let mut map = Vec::with_capacity((cells as usize).min(data.len() / 4));
+            for _ in 0..cells {
+                map.push(reader.u32()?)
+            }
+            Some((height, width, map))
+        } else {
+            None
+        };
+
+        zones.push(Zone {
+            type_,
+            leds_count,
+            matrix,
+        })
+    }
+
+    let num_leds = reader.u16()?;
+    let mut led_names = Vec::with_capacity(num_leds as usize);
+    for _ in 0..num_leds {
+        led_names.push(reader.string()?);
+        let _value = reader.u32()?;
+    }
+
+    Ok(ControllerData {
+        device_type,
+        name,
+        serial,
+        zones,
+        led_names,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, position: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.position + count > self.data.len() {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "Truncated OpenRGB packet"))
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> io::Result<()> {
+        self.take(count).map(|_| ())
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Length-prefixed, null-terminated string
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u16()? as usize;
+        let bytes = self.take(len)?;
+        let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::{Shutdown, TcpListener}, sync::mpsc::{self, Receiver}};
+
+    use crate::openrgb::{led_positions, OpenRgbState};
+
+    use super::*;
+
+    /// A packet received by the fake server: the connection it came from (counting from 0), its id and all its bytes
+    type Received = (usize, u32, Vec<u8>);
+
+    fn packet(device: u32, packet_id: PacketId, data: &[u8]) -> Vec<u8> {
+        let mut packet = MAGIC.to_vec();
+        packet.extend_from_slice(&device.to_le_bytes());
+        packet.extend_from_slice(&(packet_id as u32).to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        packet.extend_from_slice(data);
+
+        packet
+    }
+
+    fn string(data: &mut Vec<u8>, value: &str) {
+        data.extend_from_slice(&(value.len() as u16 + 1).to_le_bytes());
+        data.extend_from_slice(value.as_bytes());
+        data.push(0)
+    }
+
+    fn zone(data: &mut Vec<u8>, name: &str, type_: i32, leds_count: u32, matrix: Option<(u32, u32, &[u32])>) {
+        string(data, name);
+        data.extend_from_slice(&type_.to_le_bytes());
+        data.extend_from_slice(&leds_count.to_le_bytes());
+        data.extend_from_slice(&leds_count.to_le_bytes());
+        data.extend_from_slice(&leds_count.to_le_bytes());
+        match matrix {
+            Some((height, width, map)) => {
+                data.extend_from_slice(&((8 + map.len() * 4) as u16).to_le_bytes());
+                data.extend_from_slice(&height.to_le_bytes());
+                data.extend_from_slice(&width.to_le_bytes());
+                for led in map {
+                    data.extend_from_slice(&led.to_le_bytes())
+                }
+            },
+            None => data.extend_from_slice(&0u16.to_le_bytes()),
+        }
+    }
+
+    /// A controller (protocol version 3) with a 2x3 matrix zone that has an empty cell, a linear zone of 3 LEDs
+    /// and a single zone
+    fn controller_data() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&4i32.to_le_bytes()); // LED controller
+        for value in ["Fake controller", "Vendor", "Description", "1.0", "SERIAL", "Location"] {
+            string(&mut data, value)
+        }
+        data.extend_from_slice(&0u16.to_le_bytes()); // Modes
+        data.extend_from_slice(&0i32.to_le_bytes()); // Active mode
+        data.extend_from_slice(&3u16.to_le_bytes());
+        zone(&mut data, "Matrix", 2, 5, Some((2, 3, &[2, 0, 1, u32::MAX, 3, 4])));
+        zone(&mut data, "Strip", 1, 3, None);
+        zone(&mut data, "Logo", 0, 1, None);
+        data.extend_from_slice(&9u16.to_le_bytes());
+        for led in 0..9 {
+            string(&mut data, &format!("LED {led}"));
+            data.extend_from_slice(&0u32.to_le_bytes())
+        }
+
+        let mut packet = ((data.len() + 4) as u32).to_le_bytes().to_vec();
+        packet.extend_from_slice(&data);
+
+        packet
+    }
+
+    /// Serves `controller_data()` to every connection. The first connection is closed after its first `UpdateLeds`,
+    /// like when the server restarts
+    fn fake_server() -> (String, Receiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (connection, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                loop {
+                    let mut header = [0; HEADER_SIZE];
+                    if stream.read_exact(&mut header).is_err() {
+                        break
+                    }
+                    let mut reader = Reader::new(&header[4..]);
+                    let (device, packet_id, size) = (reader.u32().unwrap(), reader.u32().unwrap(), reader.u32().unwrap());
+                    let mut data = vec![0; size as usize];
+                    stream.read_exact(&mut data).unwrap();
+                    let reply = match packet_id {
+                        id if id == PacketId::RequestProtocolVersion as u32 => Some(packet(0, PacketId::RequestProtocolVersion, &3u32.to_le_bytes())),
+                        id if id == PacketId::RequestControllerCount as u32 => Some(packet(0, PacketId::RequestControllerCount, &1u32.to_le_bytes())),
+                        id if id == PacketId::RequestControllerData as u32 => Some(packet(device, PacketId::RequestControllerData, &controller_data())),
+                        _ => None,
+                    };
+                    if let Some(reply) = reply {
+                        stream.write_all(&reply).unwrap()
+                    }
+
+                    let closing = connection == 0 && packet_id == PacketId::UpdateLeds as u32;
+                    if closing {
+                        stream.shutdown(Shutdown::Both).unwrap()
+                    }
+                    let _ = tx.send((connection, packet_id, [&header[..], &data].concat()));
+                    if closing {
+                        break
+                    }
+                }
+            }
+        });
+
+        (address, rx)
+    }
+
+    fn next_update(rx: &Receiver<Received>) -> (usize, Vec<u8>) {
+        loop {
+            let (connection, packet_id, packet) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            if packet_id == PacketId::UpdateLeds as u32 {
+                return (connection, packet)
+            }
+        }
+    }
+
+    #[test]
+    fn bad_matrix_cells_are_skipped() {
+        let controller = |zones: Vec<Zone>, leds: usize| ControllerData {
+            device_type: 4,
+            name: "Bad controller".into(),
+            serial: "SERIAL".into(),
+            zones,
+            led_names: (0..leds).map(|led| format!("LED {led}")).collect(),
+        };
+        let ids = |controller: &ControllerData| led_positions(controller).iter().map(|led| led.id.get()).collect::<Vec<_>>();
+
+        // LEDs that aren't in the zone
+        let matrix = Zone { type_: ZoneType::Matrix, leds_count: 2, matrix: Some((2, 2, vec![1, u32::MAX - 1, 7, 0])) };
+        assert_eq!(ids(&controller(vec![matrix], 2)), [1, 0]);
+
+        // A zone so big the next one starts past the last LED id
+        let strip = Zone { type_: ZoneType::Linear, leds_count: u32::MAX - 1, matrix: None };
+        let matrix = Zone { type_: ZoneType::Matrix, leds_count: 4, matrix: Some((1, 2, vec![3, 0])) };
+        assert_eq!(ids(&controller(vec![strip, matrix], 3)), [0, 1, 2]);
+    }
+
+    #[test]
+    fn controller_leds_and_update() {
+        let (address, rx) = fake_server();
+        let mut client = OpenRgbClient::connect(&address, "test").unwrap();
+        assert_eq!(client.protocol_version, 3);
+        assert_eq!(client.controller_count().unwrap(), 1);
+
+        let controller = client.controller_data(0).unwrap();
+        assert_eq!(controller.name, "Fake controller");
+        assert_eq!(controller.serial, "SERIAL");
+        assert_eq!(controller.led_names.len(), 9);
+        let ids = led_positions(&controller).iter()
+            .map(|led| led.id.get())
+            .collect::<Vec<_>>();
+        // The matrix row by row without its empty cell, then the strip, then the logo
+        assert_eq!(ids, [2, 0, 1, 3, 4, 5, 6, 7, 8]);
+
+        client.update_leds(0, &[(255, 0, 0), (1, 2, 3)]).unwrap();
+        let (_, packet) = next_update(&rx);
+        assert_eq!(packet, [
+            b'O', b'R', b'G', b'B',
+            0, 0, 0, 0, // Controller
+            0x1A, 0x04, 0, 0, // UpdateLeds (1050)
+            14, 0, 0, 0, // Size
+            14, 0, 0, 0, // Size again
+            2, 0, // Colors
+            255, 0, 0, 0,
+            1, 2, 3, 0,
+        ]);
+    }
+
+    #[test]
+    fn reconnects_after_server_restart() {
+        let (address, rx) = fake_server();
+        let mut state = OpenRgbState::new(address);
+        state.tick();
+        assert_eq!(state.controllers.len(), 1);
+        state.tick();
+        let (connection, packet) = next_update(&rx);
+        assert_eq!(connection, 0);
+        assert_eq!(packet.len(), HEADER_SIZE + 4 + 2 + 9 * 4);
+
+        // The server closed the connection after the update
+        state.tick();
+        assert!(state.client.is_none());
+        assert!(state.controllers.is_empty());
+        state.tick();
+        assert!(state.client.is_some());
+        assert_eq!(state.controllers.len(), 1);
+        state.tick();
+        let (connection, _) = next_update(&rx);
+        assert_eq!(connection, 1);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{fmt::Write as _, io::Write, process::exit, time::{Duration, Instant}};
+
+use config_parse::get_config;
+
+use crate::{arg_value, corsair::{frame::{Desk, Frame}, output::Output, profile_effects}, layout::{Layout, KEY_SIZE}, render::{add_presses, rasterize, set_metrics, Scene}};
+
+/// Terminal columns used by a 1u key
+const COLUMNS_PER_KEY: f64 = 4.0;
+/// Terminal lines used by a 1u key. Terminal cells are about twice as tall as they are wide
+const LINES_PER_KEY: f64 = 2.0;
+
+/// `preview [--profile NAME] [--layout NAME] [--presses KEY@MILLIS,...] [--metrics NAME=VALUE,...]`: draws the lights of a profile in the terminal instead of sending them to a device
+pub(crate) fn preview(args: &[String]) {
+    let profile_name = arg_value(args, "--profile").unwrap_or("default".into());
+    let config = get_config();
+    let profile = match config.profiles.iter().find(|profile| profile.name == profile_name) {
+        Some(profile) => profile,
+        None => {
+            eprintln!("Profile {profile_name} doesn't exist");
+            exit(1)
+        },
+    };
+
+    set_metrics(args);
+    let mut effects = profile_effects(profile, Duration::ZERO);
+    add_presses(args, &mut effects);
+    let layouts = match Layout::load(&arg_value(args, "--layout").unwrap_or("full_size".into())) {
+        Ok(layouts) => layouts,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1)
+        },
+    };
+    let mut scene = Scene::new(&layouts, &Desk { placements: config.desk.clone() }, Output::new(&config.settings));
+
+    ctrlc::set_handler(|| {
+        // Restore the colors and the cursor
+        println!("\x1b[0m\x1b[?25h");
+        exit(0)
+    }).unwrap();
+    print!("\x1b[2J\x1b[?25l");
+
+    let frame_time = Duration::from_secs(1) / config.settings.fps;
+    let start_time = Instant::now();
+    loop {
+        let frame_start = Instant::now();
+        let leds = scene.render(&effects, start_time.elapsed());
+        let frame = draw(&scene.layout, &leds);
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(frame.as_bytes()).unwrap();
+        stdout.flush().unwrap();
+        drop(stdout);
+
+        std::thread::sleep(frame_time.saturating_sub(frame_start.elapsed()))
+    }
+}
+
+/// Paints every LED as a truecolor block the size of its key
+fn draw(layout: &Layout, leds: &Frame) -> String {
+    let column_size = KEY_SIZE / COLUMNS_PER_KEY;
+    let line_size = KEY_SIZE / LINES_PER_KEY;
+    // Leave the last column of each key empty, so neighbouring keys can be told apart
+    let raster = rasterize(layout, leds, (column_size, line_size), (column_size, 0.0));
+
+    let mut frame = format!("\x1b[H{}\n", layout.model);
+    for line in raster.cells.chunks(raster.width.max(1)) {
+        for cell in line {
+            match cell {
+                Some((r, g, b)) => write!(frame, "\x1b[48;2;{r};{g};{b}m ").unwrap(),
+                None => frame.push_str("\x1b[0m "),
+            }
+        }
+        frame.push_str("\x1b[0m\n");
+    }
+
+    frame
+}
@@ -0,0 +1,338 @@
+use std::{fs::File, io::{BufWriter, ErrorKind}, path::Path, process::exit, time::Duration};
+
+use cgmath::Angle;
+use config_parse::{get_config, Profile};
+use icue_bindings::types::CorsairLedLuid;
+use sis_core::VirtualKey;
+
+use crate::{arg_value, corsair::{compositor::Compositor, frame::{Desk, DeviceInfo, EffectStack, Frame, PressedKey}, output::Output, profile_effects}, layout::{Layout, LayoutLed}, metrics::{start_sampling, FixedMetrics, Metrics}};
+
+const PIXELS_PER_MM: f64 = 2.0;
+/// Empty space between 2 keys, in mm
+const KEY_GAP: f64 = 2.0;
+const BACKGROUND: (u8, u8, u8) = (24, 24, 24);
+
+/// A layout painted on a grid of cells, where cells without a key are `None`
+pub(crate) struct Raster {
+    pub(crate) width: usize,
+    pub(crate) cells: Vec<Option<(u8, u8, u8)>>,
+}
+
+/// Every device of a layout file, placed on the desk so they can be drawn together
+pub(crate) struct Scene {
+    devices: Vec<(DeviceInfo, Compositor)>,
+    /// Brightness, gamma and calibration, so the frames look like the devices
+    output: Output,
+    /// Every LED of every device (moved so nothing is left or above 0), with its index as id
+    pub(crate) layout: Layout,
+}
+
+impl Scene {
+    pub(crate) fn new(layouts: &[Layout], desk: &Desk, output: Output) -> Scene {
+        let devices = layouts.iter()
+            .map(|layout| (layout.device_info(), layout.positions()))
+            .collect::<Vec<_>>();
+        let compositors = desk.compositors(devices.iter().map(|(info, positions)| (info, positions.as_slice())));
+        let mut leds = Vec::new();
+        for (layout, (info, positions)) in layouts.iter().zip(devices.iter()) {
+            let placed = desk.place(info, positions);
+            let (scale, sin, cos) = match desk.placement(info) {
+                Some(placement) => (placement.scale, placement.rotation.sin().abs() as f64, placement.rotation.cos().abs() as f64),
+                None => (1.0, 0.0, 1.0),
+            };
+            for (led, position) in layout.leds.iter().zip(placed.iter()) {
+                // Rotated LEDs are drawn as the rectangle around them
+                let (width, height) = (led.width * scale, led.height * scale);
+                leds.push(LayoutLed {
+                    id: CorsairLedLuid::new(leds.len() as u32),
+                    name: led.name.clone(),
+                    cx: position.cx,
+                    cy: position.cy,
+                    width: width * cos + height * sin,
+                    height: width * sin + height * cos,
+                })
+            }
+        }
+
+        let left = leds.iter().map(|led| led.cx - led.width / 2.0).fold(f64::INFINITY, f64::min);
+        let top = leds.iter().map(|led| led.cy - led.height / 2.0).fold(f64::INFINITY, f64::min);
+        for led in leds.iter_mut() {
+            led.cx -= left;
+            led.cy -= top;
+        }
+
+        Scene {
+            devices: devices.into_iter().map(|(info, _)| info).zip(compositors).collect(),
+            output,
+            layout: Layout {
+                model: layouts.iter().map(|layout| layout.model.as_str()).collect::<Vec<_>>().join(", "),
+                device_type: None,
+                leds,
+            },
+        }
+    }
+
+    /// The frame of every device, as sent to the device, with the ids of `self.layout`
+    pub(crate) fn render(&mut self, effects: &EffectStack, t: Duration) -> Frame {
+        let mut frame = Vec::with_capacity(self.layout.leds.len());
+        for (info, compositor) in self.devices.iter_mut() {
+            let first = frame.len();
+            let device_frame = compositor.render(info, effects, t);
+            self.output.apply(info, device_frame);
+            frame.extend(device_frame.iter()
+                .enumerate()
+                .map(|(index, &(_, color))| (CorsairLedLuid::new((first + index) as u32), color)))
+        }
+
+        frame
+    }
+}
+
+/// Paints every LED as a rectangle the size of its key.
+///
+/// `cell_size` is the (width, height) in mm of a single cell, `gap` the (horizontal, vertical) mm left empty after each key.
+pub(crate) fn rasterize(layout: &Layout, leds: &Frame, cell_size: (f64, f64), gap: (f64, f64)) -> Raster {
+    let (cell_width, cell_height) = cell_size;
+    let (width, height) = raster_size(layout, cell_size);
+    let mut cells = vec![None; width * height];
+    for led in layout.leds.iter() {
+        let color = match leds.iter().find(|(id, _)| *id == led.id) {
+            Some((_, (r, g, b, _))) => (*r, *g, *b),
+            None => continue,
+        };
+        let left = ((led.cx - led.width / 2.0) / cell_width).round() as usize;
+        let right = ((led.cx + led.width / 2.0 - gap.0) / cell_width).round().max(0.0) as usize;
+        let top = ((led.cy - led.height / 2.0) / cell_height).round() as usize;
+        let bottom = ((led.cy + led.height / 2.0 - gap.1) / cell_height).round().max(0.0) as usize;
+        for line in top..bottom.max(top + 1).min(height) {
+            for column in left..right.max(left + 1).min(width) {
+                cells[line * width + column] = Some(color)
+            }
+        }
+    }
+
+    Raster {
+        width,
+        cells,
+    }
+}
+
+/// The (width, height) in cells of the raster of `layout`
+fn raster_size(layout: &Layout, (cell_width, cell_height): (f64, f64)) -> (usize, usize) {
+    let width = layout.leds.iter()
+        .map(|led| ((led.cx + led.width / 2.0) / cell_width).ceil() as usize)
+        .max()
+        .unwrap_or(0);
+    let height = layout.leds.iter()
+        .map(|led| ((led.cy + led.height / 2.0) / cell_height).ceil() as usize)
+        .max()
+        .unwrap_or(0);
+
+    (width, height)
+}
+
+/// `render --profile NAME [--layout NAME] [--presses KEY@MILLIS,...] [--metrics NAME=VALUE,...] [--switch PROFILE@MILLIS] --duration MILLIS --fps FPS --output FILE`
+///
+/// Writes an animated GIF if the output ends with `.gif`, otherwise a directory of numbered PNG frames.
+pub(crate) fn render(args: &[String]) {
+    let profile_name = arg_value(args, "--profile").unwrap_or("default".into());
+    let layout_name = arg_value(args, "--layout").unwrap_or("full_size".into());
+    let duration: u64 = parse_arg(args, "--duration");
+    let fps: u64 = parse_arg(args, "--fps");
+    let output = match arg_value(args, "--output") {
+        Some(output) => output,
+        None => {
+            eprintln!("Missing --output");
+            exit(1)
+        },
+    };
+    if fps == 0 {
+        eprintln!("--fps must be bigger than 0");
+        exit(1)
+    }
+
+    let config = get_config();
+    let profile = match config.profiles.iter().find(|profile| profile.name == profile_name) {
+        Some(profile) => profile,
+        None => {
+            eprintln!("Profile {profile_name} doesn't exist");
+            exit(1)
+        },
+    };
+    let layouts = match Layout::load(&layout_name) {
+        Ok(layouts) => layouts,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1)
+        },
+    };
+    let mut scene = Scene::new(&layouts, &Desk { placements: config.desk.clone() }, Output::new(&config.settings));
+
+    set_metrics(args);
+    let mut effects = profile_effects(profile, Duration::ZERO);
+    add_presses(args, &mut effects);
+    let mut switch = profile_switch(args, &config.profiles);
+    let frame_count = duration * fps / 1000;
+    let frames = (0..frame_count).map(|frame| {
+        let t = Duration::from_millis(frame * 1000 / fps);
+        if let Some((next_profile, time)) = switch.filter(|(_, time)| *time <= t) {
+            let next_effects = profile_effects(next_profile, time);
+            effects.fade_out(time, config.settings.profile_fade);
+            effects.effects = next_effects.effects;
+            effects.key_effects = next_effects.key_effects;
+            switch = None;
+        }
+        let leds = scene.render(&effects, t);
+        to_pixels(&scene.layout, &leds)
+    });
+
+    let res = if output.ends_with(".gif") {
+        write_gif(Path::new(&output), frames, fps)
+    } else {
+        write_pngs(Path::new(&output), frames)
+    };
+    if let Err(err) = res {
+        eprintln!("Couldn't write {output}: {err}");
+        exit(1)
+    }
+    println!("Rendered {frame_count} frames to {output}");
+}
+
+/// `--presses W@100,A@250`: presses `W` 100ms after the effects start, and `A` after 250ms.
+/// Used to see the effects that react to key presses without typing.
+pub(crate) fn add_presses(args: &[String], effects: &mut EffectStack) {
+    let presses = match arg_value(args, "--presses") {
+        Some(presses) => presses,
+        None => return,
+    };
+    let mut presses = presses.split(',')
+        .map(|press| {
+            let (key, millis) = press.split_once('@')?;
+            let key = VirtualKey::try_from(key).ok()?;
+            let millis = millis.parse().ok()?;
+            Some((key, Duration::from_millis(millis)))
+        }).collect::<Option<Vec<_>>>()
+        .unwrap_or_else(|| {
+            eprintln!("--presses must be a list of KEY@MILLIS, like W@100,A@250");
+            exit(1)
+        });
+
+    presses.sort_by_key(|(_, time)| *time);
+    for (key, time) in presses {
+        effects.press_key(PressedKey::Key(key), time)
+    }
+}
+
+/// `--metrics cpu=0.5,memory=0.25,temperature=70`: gauges show these values instead of the ones of this computer.
+/// Metrics that aren't given are unknown. It has to be called before the effects are created
+pub(crate) fn set_metrics(args: &[String]) {
+    let values = match arg_value(args, "--metrics") {
+        Some(values) => values,
+        None => return,
+    };
+    let mut metrics = Metrics::default();
+    for value in values.split(',') {
+        let parsed = value.split_once('=')
+            .and_then(|(name, value)| Some((name, value.parse::<f32>().ok()?)));
+        match parsed {
+            Some(("cpu", value)) => metrics.cpu = Some(value),
+            Some(("memory", value)) => metrics.memory = Some(value),
+            Some(("temperature", value)) => metrics.temperature = Some(value),
+            _ => {
+                eprintln!("--metrics must be a list of cpu=LOAD, memory=LOAD or temperature=CELSIUS, like cpu=0.5,temperature=70");
+                exit(1)
+            },
+        }
+    }
+
+    start_sampling(FixedMetrics(metrics))
+}
+
+/// `--switch gaming@1000`: switches to the profile `gaming` 1000ms after the effects start, with the fade of the settings
+fn profile_switch<'a>(args: &[String], profiles: &'a [Profile]) -> Option<(&'a Profile, Duration)> {
+    let switch = arg_value(args, "--switch")?;
+    let parsed = switch.split_once('@').and_then(|(name, millis)| {
+        let profile = profiles.iter().find(|profile| profile.name == name)?;
+        Some((profile, Duration::from_millis(millis.parse().ok()?)))
+    });
+    if parsed.is_none() {
+        eprintln!("--switch must be an existing profile and the time of the switch, like gaming@1000");
+        exit(1)
+    }
+
+    parsed
+}
+
+fn parse_arg(args: &[String], flag: &str) -> u64 {
+    match arg_value(args, flag).map(|value| value.parse()) {
+        Some(Ok(value)) => value,
+        _ => {
+            eprintln!("{flag} must be a positive integer");
+            exit(1)
+        },
+    }
+}
+
+/// (width, height, RGB bytes)
+/// The width, height and RGB pixels of a frame. GIF sizes are 16 bit, so frames are never bigger than that
+fn to_pixels(layout: &Layout, leds: &Frame) -> std::io::Result<(u16, u16, Vec<u8>)> {
+    let cell_size = (1.0 / PIXELS_PER_MM, 1.0 / PIXELS_PER_MM);
+    // Checked before painting, a layout this big wouldn't even fit in memory
+    let (raster_width, raster_height) = raster_size(layout, cell_size);
+    let (width, height) = match (u16::try_from(raster_width), u16::try_from(raster_height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("the layout is {raster_width}x{raster_height} pixels, frames can't be bigger than {max}x{max} pixels", max = u16::MAX),
+        )),
+    };
+    let raster = rasterize(layout, leds, cell_size, (KEY_GAP, KEY_GAP));
+    let pixels = raster.cells.iter()
+        .flat_map(|cell| {
+            let (r, g, b) = cell.unwrap_or(BACKGROUND);
+            [r, g, b]
+        }).collect();
+
+    Ok((width, height, pixels))
+}
+
+fn write_gif(path: &Path, frames: impl Iterator<Item=std::io::Result<(u16, u16, Vec<u8>)>>, fps: u64) -> std::io::Result<()> {
+    let mut frames = frames.peekable();
+    let (width, height) = match frames.peek() {
+        Some(Ok((width, height, _))) => (*width, *height),
+        // Before creating the file
+        Some(Err(_)) => return frames.next().unwrap().map(|_| ()),
+        None => (0, 0),
+    };
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = gif::Encoder::new(file, width, height, &[]).map_err(std::io::Error::other)?;
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(std::io::Error::other)?;
+    // GIF delays are in hundredths of a second, each frame lasts until the next one is due so the GIF isn't
+    // longer or shorter than the render. Frames can't last less than one hundredth
+    let mut elapsed = 0;
+    for (index, frame) in frames.enumerate() {
+        let (width, height, pixels) = frame?;
+        let mut frame = gif::Frame::from_rgb_speed(width, height, &pixels, 10);
+        let end = ((index as u64 + 1) * 100 + fps / 2) / fps;
+        frame.delay = end.saturating_sub(elapsed).max(1) as u16;
+        elapsed += frame.delay as u64;
+        encoder.write_frame(&frame).map_err(std::io::Error::other)?;
+    }
+
+    Ok(())
+}
+
+fn write_pngs(directory: &Path, frames: impl Iterator<Item=std::io::Result<(u16, u16, Vec<u8>)>>) -> std::io::Result<()> {
+    std::fs::create_dir_all(directory)?;
+    for (index, frame) in frames.enumerate() {
+        let (width, height, pixels) = frame?;
+        let file = BufWriter::new(File::create(directory.join(format!("frame_{index:05}.png")))?);
+        let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+        writer.write_image_data(&pixels).map_err(std::io::Error::other)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! When the lighting threads render their frames
+
+use std::{sync::mpsc::{Receiver, RecvTimeoutError}, time::{Duration, Instant}};
+
+/// How often slow rendering is reported
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How the lighting thread waits for its next frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Pace {
+    /// Frames are rendered at the frame rate
+    Animated,
+    /// Nothing changes on its own, the next frame is rendered after the next message
+    Idle,
+    /// Like `Idle`, but a frame is rendered after this long anyway
+    IdleFor(Duration),
+}
+
+/// Why `FrameScheduler::wait` returned
+pub(crate) enum Wake<T> {
+    Message(T),
+    Frame,
+}
+
+/// Frames are due every frame time from the first one, so the frame rate doesn't drift with the time it takes
+/// to render them. Frames that can't be shown on time are skipped, instead of being rendered in a burst to catch up.
+#[derive(Debug)]
+pub(crate) struct FrameScheduler {
+    frame_time: Duration,
+    next_frame: Instant,
+    stats: FrameStats,
+}
+
+/// The frames since the last report
+#[derive(Debug)]
+struct FrameStats {
+    start: Instant,
+    frames: u32,
+    skipped: u32,
+    total_time: Duration,
+    slowest: Duration,
+}
+
+impl FrameStats {
+    fn new() -> FrameStats {
+        FrameStats {
+            start: Instant::now(),
+            frames: 0,
+            skipped: 0,
+            total_time: Duration::ZERO,
+            slowest: Duration::ZERO,
+        }
+    }
+}
+
+impl FrameScheduler {
+    pub(crate) fn new(fps: u32) -> FrameScheduler {
+        FrameScheduler {
+            frame_time: frame_time(fps),
+            next_frame: Instant::now(),
+            stats: FrameStats::new(),
+        }
+    }
+
+    pub(crate) fn set_fps(&mut self, fps: u32) {
+        self.frame_time = frame_time(fps);
+        self.next_frame = Instant::now();
+    }
+
+    /// Waits until the next frame is due, or until a message comes. Panics if the channel is closed
+    pub(crate) fn wait<T>(&mut self, rx: &Receiver<T>, pace: Pace) -> Wake<T> {
+        let res = match pace {
+            // A due frame goes first, so a stream of messages can't hold the animation back
+            Pace::Animated if Instant::now() >= self.next_frame => return Wake::Frame,
+            Pace::Animated => rx.recv_timeout(self.next_frame.saturating_duration_since(Instant::now())),
+            Pace::Idle => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            Pace::IdleFor(timeout) => rx.recv_timeout(timeout),
+        };
+        if pace != Pace::Animated {
+            // What the message changes is shown right away, and the time spent idle isn't skipped frames
+            self.next_frame = Instant::now();
+        }
+        match res {
+            Ok(msg) => Wake::Message(msg),
+            Err(RecvTimeoutError::Timeout) => Wake::Frame,
+            Err(RecvTimeoutError::Disconnected) => panic!("Channel closed"),
+        }
+    }
+
+    /// Schedules the next frame, `start` is when rendering the last one started
+    pub(crate) fn frame_done(&mut self, start: Instant) {
+        let now = Instant::now();
+        let render_time = now - start;
+        self.stats.frames += 1;
+        self.stats.total_time += render_time;
+        self.stats.slowest = self.stats.slowest.max(render_time);
+
+        self.next_frame += self.frame_time;
+        if self.next_frame < now {
+            // Too late for these frames, the next one is the first that can still be on time
+            let late = ((now - self.next_frame).as_nanos() / self.frame_time.as_nanos()) as u32 + 1;
+            self.next_frame += self.frame_time * late;
+            self.stats.skipped += late;
+        }
+
+        if self.stats.start.elapsed() >= STATS_INTERVAL {
+            let FrameStats { frames, skipped, total_time, slowest, .. } = self.stats;
+            if skipped > 0 {
+                eprintln!(
+                    "Rendering is too slow for {:.0} fps: {skipped} frames skipped in the last {STATS_INTERVAL:?}, frames took {:?} on average and {slowest:?} at most",
+                    1.0 / self.frame_time.as_secs_f64(),
+                    total_time / frames,
+                );
+            }
+            self.stats = FrameStats::new();
+        }
+    }
+}
+
+fn frame_time(fps: u32) -> Duration {
+    Duration::from_secs(1) / fps.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    #[test]
+    fn due_frames_go_before_messages() {
+        let (tx, rx) = channel();
+        let mut scheduler = FrameScheduler::new(20);
+        for msg in 0..10 {
+            tx.send(msg).unwrap();
+        }
+
+        // The first frame is due right away
+        assert!(matches!(scheduler.wait(&rx, Pace::Animated), Wake::Frame));
+        scheduler.frame_done(Instant::now());
+        assert!(matches!(scheduler.wait(&rx, Pace::Animated), Wake::Message(0)));
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(matches!(scheduler.wait(&rx, Pace::Animated), Wake::Frame));
+        // Messages still come first when nothing is animated
+        assert!(matches!(scheduler.wait(&rx, Pace::Idle), Wake::Message(1)));
+    }
+}